@@ -0,0 +1,506 @@
+use crate::{parser::code_atoms, Config, Definition, LookupTable, RegexAst};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Example
+/// an input leading to a dfa state, lexed in start condition `condition`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Example {
+    pub text: Vec<u8>,
+    pub condition: usize,
+}
+
+impl Example {
+    /// describe
+    /// the text quoted, with its start condition unless it is INITIAL
+    pub fn describe(&self, config: &Config) -> String {
+        let text = match std::str::from_utf8(&self.text) {
+            Ok(text) => text.escape_debug().to_string(),
+            Err(_) => self.text.escape_ascii().to_string(),
+        };
+
+        match self.condition {
+            0 => format!("\"{}\"", text),
+            condition => format!("\"{}\" in <{}>", text, config.conditions[condition].name),
+        }
+    }
+}
+
+/// Lint
+/// a problem found by check, rules are indexes of config.rules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lint {
+    /// a definition no rule references, directly or through other definitions
+    UnusedDefinition { name: String },
+    /// the rule matches no input in any start condition it is active in
+    UnreachableRule { rule: usize },
+    /// every input the rule matches is also matched by an earlier rule,
+    /// example is one of them, won by winner
+    ShadowedRule {
+        rule: usize,
+        winner: usize,
+        example: Example,
+    },
+    /// the rule wins on some inputs but loses example to winner
+    Overlap {
+        rule: usize,
+        winner: usize,
+        example: Example,
+    },
+    /// the rule matches the empty string in start condition `condition`,
+    /// the start state of the condition accepts
+    EmptyMatch { rule: usize, condition: usize },
+    /// the handler keeps none of the text and does not change the input or condition,
+    /// the lexer may match the same text forever
+    /// found from the method calls written in the handler, whichever branch they are in
+    NoProgress { rule: usize },
+}
+
+impl Lint {
+    /// is_warning
+    /// overlaps are expected, e.g. keywords and identifiers, and are only notes
+    pub fn is_warning(&self) -> bool {
+        !matches!(self, Lint::Overlap { .. })
+    }
+
+    /// message
+    /// the lint as a sentence, rules are shown as written
+    pub fn message(&self, config: &Config) -> String {
+        match self {
+            Lint::UnusedDefinition { name } => format!("definition \"{}\" is never used", name),
+            Lint::UnreachableRule { rule } => format!(
+                "rule `{}` never matches any input",
+                config.describe_rule(*rule)
+            ),
+            Lint::ShadowedRule {
+                rule,
+                winner,
+                example,
+            } => format!(
+                "rule `{}` never matches, rules before it match every input it does, e.g. `{}` wins on {}",
+                config.describe_rule(*rule),
+                config.describe_rule(*winner),
+                example.describe(config)
+            ),
+            Lint::Overlap {
+                rule,
+                winner,
+                example,
+            } => format!(
+                "rule `{}` overlaps rule `{}`, which wins on {}",
+                config.describe_rule(*rule),
+                config.describe_rule(*winner),
+                example.describe(config)
+            ),
+            Lint::EmptyMatch { rule, condition } => format!(
+                "rule `{}` matches the empty string{}, the lexer never runs a rule on an empty match",
+                config.describe_rule(*rule),
+                match condition {
+                    0 => String::new(),
+                    condition => format!(" in <{}>", config.conditions[*condition].name),
+                }
+            ),
+            Lint::NoProgress { rule } => format!(
+                "rule `{}` calls yyless(0) without begin, unput or push_input, the lexer may match the same text forever",
+                config.describe_rule(*rule)
+            ),
+        }
+    }
+}
+
+/// check
+/// find unused definitions, rules that never win and rules overlapping earlier ones
+/// table is the lookup table built from config, the lowest handler of a state wins it
+pub fn check(config: &Config, table: &LookupTable) -> Vec<Lint> {
+    let mut res: Vec<Lint> = unused_definitions(config)
+        .into_iter()
+        .map(|name| Lint::UnusedDefinition { name })
+        .collect();
+    let progress = progress_lints(config, table);
+
+    // 按例子从短到长遍历, 每对规则只记录最短的例子
+    let mut wins = HashSet::new();
+    let mut losses: Vec<(usize, usize, Example)> = Vec::new();
+    for (example, handlers) in accepting_states(table) {
+        let winner = handlers[0];
+        wins.insert(winner);
+        for rule in handlers[1..].iter() {
+            if !losses.iter().any(|(r, w, _)| r == rule && *w == winner) {
+                losses.push((*rule, winner, example.clone()));
+            }
+        }
+    }
+
+    for (rule, each) in config.rules.iter().enumerate() {
+        if each.eof {
+            continue;
+        }
+
+        let mut rule_losses = losses.iter().filter(|(r, _, _)| *r == rule).peekable();
+        if wins.contains(&rule) {
+            res.extend(rule_losses.map(|(rule, winner, example)| Lint::Overlap {
+                rule: *rule,
+                winner: *winner,
+                example: example.clone(),
+            }));
+        } else if let Some((_, winner, example)) = rule_losses.peek() {
+            res.push(Lint::ShadowedRule {
+                rule,
+                winner: *winner,
+                example: example.clone(),
+            });
+        } else if !progress
+            .iter()
+            .any(|lint| matches!(lint, Lint::EmptyMatch { rule: r, .. } if *r == rule))
+        {
+            res.push(Lint::UnreachableRule { rule });
+        }
+    }
+    res.extend(progress);
+
+    res
+}
+
+/// Examples
+/// rules are indexes of config.rules
+/// rules: the shortest input each rule wins on, None if it never wins or is an `<<EOF>>` rule
+/// conflicts: every pair of rules matching a same input, with the shortest such input
+pub struct Examples {
+    pub rules: Vec<Option<Example>>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Conflict
+/// first and second, first < second, both match example, winner is the rule run on it
+/// winner is first unless a rule before both matches example too
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub first: usize,
+    pub second: usize,
+    pub winner: usize,
+    pub example: Example,
+}
+
+/// examples
+/// the shortest input of each rule and each conflict, for reviewing a config
+/// table is the lookup table built from config
+pub fn examples(config: &Config, table: &LookupTable) -> Examples {
+    let mut rules: Vec<Option<Example>> = vec![None; config.rules.len()];
+    let mut conflicts: Vec<Conflict> = Vec::new();
+    for (example, handlers) in accepting_states(table) {
+        let winner = handlers[0];
+        if rules[winner].is_none() {
+            rules[winner] = Some(example.clone());
+        }
+
+        for (index, first) in handlers.iter().enumerate() {
+            for second in handlers[index + 1..].iter() {
+                let found = conflicts
+                    .iter()
+                    .any(|each| each.first == *first && each.second == *second);
+                if !found {
+                    conflicts.push(Conflict {
+                        first: *first,
+                        second: *second,
+                        winner,
+                        example: example.clone(),
+                    });
+                }
+            }
+        }
+    }
+    conflicts.sort_by_key(|each| (each.first, each.second));
+
+    Examples { rules, conflicts }
+}
+
+/// accepting_states
+/// the example and the sorted handlers of every accepting state some input reaches,
+/// shortest and most readable examples first
+fn accepting_states(table: &LookupTable) -> Vec<(Example, Vec<usize>)> {
+    let mut res: Vec<(Example, Vec<usize>)> = shortest_examples(table)
+        .into_iter()
+        .zip(table.states.iter())
+        .filter(|(_, state)| !state.handlers.is_empty())
+        .filter_map(|(example, state)| {
+            let mut handlers: Vec<usize> = state.handlers.iter().copied().collect();
+            handlers.sort_unstable();
+            example.map(|example| (example, handlers))
+        })
+        .collect();
+    res.sort_by_cached_key(|(example, _)| {
+        let key: Vec<_> = example.text.iter().map(|c| (readability(*c), *c)).collect();
+        (example.text.len(), key)
+    });
+
+    res
+}
+
+/// progress_lints
+/// rules that match the empty string or may stop the lexer from consuming input
+/// checked when generating code too
+pub fn progress_lints(config: &Config, table: &LookupTable) -> Vec<Lint> {
+    let mut res = Vec::new();
+
+    // 开始状态可接受, 即规则匹配空串
+    let mut empty: Vec<(usize, usize)> = Vec::new();
+    for (condition, start) in table.starts.iter().enumerate() {
+        for rule in table.states[*start].handlers.iter() {
+            if !empty.iter().any(|(r, _)| r == rule) {
+                empty.push((*rule, condition));
+            }
+        }
+    }
+    empty.sort_unstable();
+    res.extend(
+        empty
+            .into_iter()
+            .map(|(rule, condition)| Lint::EmptyMatch { rule, condition }),
+    );
+
+    // yyless(0)之后输入不变, 除非handler改变了输入或start condition
+    // 按handler的token找调用, 字符串和注释中的不算
+    for (rule, each) in config.rules.iter().enumerate() {
+        let atoms = code_atoms(&each.handler);
+        let calls = |call: &[&str]| atoms.windows(call.len()).any(|atoms| atoms == call);
+        if calls(&[".", "yyless", "(", "0", ")"])
+            && !["begin", "unput", "push_input"]
+                .iter()
+                .any(|name| calls(&[".", name, "("]))
+        {
+            res.push(Lint::NoProgress { rule });
+        }
+    }
+
+    res
+}
+
+/// shortest_examples
+/// a shortest non-empty input reaching each state from any start state,
+/// None for states no input reaches
+/// letters and digits are preferred, so examples are readable
+pub fn shortest_examples(table: &LookupTable) -> Vec<Option<Example>> {
+    let mut res: Vec<Option<Example>> = vec![None; table.states.len()];
+    let mut expanded = vec![false; table.states.len()];
+    let mut queue: VecDeque<(usize, Example)> = table
+        .starts
+        .iter()
+        .enumerate()
+        .map(|(condition, start)| {
+            let example = Example {
+                text: Vec::new(),
+                condition,
+            };
+            (*start, example)
+        })
+        .collect();
+
+    while let Some((state, example)) = queue.pop_front() {
+        if expanded[state] {
+            continue;
+        }
+        expanded[state] = true;
+
+        let mut neighbors: Vec<(u8, usize)> = table.states[state]
+            .neighbors
+            .iter()
+            .map(|(c, next)| (*c, *next))
+            .collect();
+        neighbors.sort_by_key(|(c, _)| (readability(*c), *c));
+
+        for (c, next) in neighbors {
+            if res[next].is_some() {
+                continue;
+            }
+
+            let mut text = example.text.clone();
+            text.push(c);
+            let next_example = Example {
+                text,
+                condition: example.condition,
+            };
+            res[next] = Some(next_example.clone());
+            queue.push_back((next, next_example));
+        }
+    }
+
+    res
+}
+
+/// readability
+/// lower is more readable
+fn readability(c: u8) -> u8 {
+    match c {
+        c if c.is_ascii_alphanumeric() => 0,
+        c if c.is_ascii_graphic() || c == b' ' => 1,
+        c if c.is_ascii() => 2,
+        _ => 3,
+    }
+}
+
+/// unused_definitions
+/// definitions not reachable from the patterns of the rules
+/// definitions of included files are shared and may be unused
+fn unused_definitions(config: &Config) -> Vec<String> {
+    let definitions: HashMap<&str, &Definition> = config
+        .definitions
+        .iter()
+        .map(|each| (each.name.as_str(), each))
+        .collect();
+
+    // 从规则出发, 标记所有引用到的definition
+    let mut used: HashSet<String> = HashSet::new();
+    let mut pending: Vec<String> = config
+        .rules
+        .iter()
+        .filter(|rule| !rule.eof)
+        .flat_map(|rule| references(&rule.pattern))
+        .collect();
+    while let Some(name) = pending.pop() {
+        if !used.insert(name.clone()) {
+            continue;
+        }
+        // 参数不是definition
+        if let Some(definition) = definitions.get(name.as_str()) {
+            pending.extend(
+                references(&definition.pattern)
+                    .into_iter()
+                    .filter(|reference| !definition.params.contains(reference)),
+            );
+        }
+    }
+
+    config
+        .definitions
+        .iter()
+        .filter(|each| !each.included && !used.contains(&each.name))
+        .map(|each| each.name.clone())
+        .collect()
+}
+
+fn references(pattern: &str) -> Vec<String> {
+    match RegexAst::parse(pattern) {
+        Ok(ast) => ast
+            .references()
+            .into_iter()
+            .map(|(name, _)| name.to_string())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_dfa, test_util::parse_text};
+
+    /// lints
+    /// the messages of check on a .rlex text
+    fn lints(text: &str) -> Vec<String> {
+        let config = parse_text(text).unwrap();
+        let table = build_dfa(&config).unwrap().lookup_table;
+        check(&config, &table)
+            .iter()
+            .map(|lint| lint.message(&config))
+            .collect()
+    }
+
+    /// spec
+    /// a .rlex text with definitions and rules
+    fn spec(definitions: &str, rules: &str) -> String {
+        format!("%{{\n%}}\n{}\n%%\n{}\n%%\n", definitions, rules)
+    }
+
+    #[test]
+    fn shadowed_and_overlapping_rules() {
+        let text = spec(
+            "",
+            "    if -> |_s| {} ;;\n    [a-z]+ -> |_s| {} ;;\n    iff -> |_s| {} ;;",
+        );
+        assert_eq!(
+            lints(&text),
+            vec![
+                "rule `[a-z]+` overlaps rule `if`, which wins on \"if\"",
+                "rule `iff` never matches, rules before it match every input it does, e.g. `[a-z]+` wins on \"iff\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn unused_definitions() {
+        let text = spec(
+            "    Digit = [0-9]\n    Number = {Digit}+\n    Pair(a) = {a}{a}\n    Unused = x",
+            "    {Pair({Number})} -> |_s| {} ;;",
+        );
+        assert_eq!(lints(&text), vec!["definition \"Unused\" is never used"]);
+    }
+
+    #[test]
+    fn no_progress() {
+        let rule = |handler: &str| lints(&spec("", &format!("    a -> {} ;;", handler)));
+        let message = "rule `a` calls yyless(0) without begin, unput or push_input, the lexer may match the same text forever";
+
+        assert_eq!(rule("|ctx, _s| { ctx.yyless(0); }"), vec![message]);
+        assert_eq!(rule("|ctx, _s| { ctx.yyless( 0 ); }"), vec![message]);
+        assert!(rule("|ctx, _s| { ctx.yyless(1); }").is_empty());
+        // 注释和字符串中的不算调用
+        assert!(rule("|ctx, _s| { // ctx.yyless(0);\n }").is_empty());
+        assert!(rule("|ctx, _s| { println!(\"ctx.yyless(0)\"); }").is_empty());
+        assert!(rule("|ctx, _s| { ctx.yyless(0); ctx.begin(INITIAL); }").is_empty());
+        assert!(rule("|ctx, _s| { ctx.yyless(0); /* ctx.begin(INITIAL); */ }").len() == 1);
+    }
+
+    #[test]
+    fn empty_matches() {
+        let text = spec(
+            "    %x STR",
+            "    <STR>a* -> |_s| {} ;;\n    b -> |_s| {} ;;",
+        );
+        assert_eq!(
+            lints(&text),
+            vec!["rule `<STR>a*` matches the empty string in <STR>, the lexer never runs a rule on an empty match"]
+        );
+    }
+
+    #[test]
+    fn shortest_examples_and_conflicts() {
+        let text = spec(
+            "",
+            "    if -> |_s| {} ;;\n    [a-z]+ -> |_s| {} ;;\n    [0-9]+ -> |_s| {} ;;\n    [^\\n]|i -> |_s| {} ;;",
+        );
+        let config = parse_text(&text).unwrap();
+        let table = build_dfa(&config).unwrap().lookup_table;
+        let examples = examples(&config, &table);
+
+        let rules: Vec<_> = examples
+            .rules
+            .iter()
+            .map(|example| example.as_ref().map(|example| example.describe(&config)))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                Some("\"if\"".to_string()),
+                Some("\"a\"".to_string()),
+                Some("\"0\"".to_string()),
+                Some("\"A\"".to_string()),
+            ]
+        );
+
+        let conflicts: Vec<_> = examples
+            .conflicts
+            .iter()
+            .map(|each| {
+                let text = each.example.describe(&config);
+                (each.first, each.second, each.winner, text)
+            })
+            .collect();
+        assert_eq!(
+            conflicts,
+            vec![
+                (0, 1, 0, "\"if\"".to_string()),
+                (1, 3, 1, "\"a\"".to_string()),
+                (2, 3, 2, "\"0\"".to_string()),
+            ]
+        );
+    }
+}
@@ -1,134 +1,1519 @@
-use crate::LookupTable;
+use crate::{Backend, Config, ErrorPolicy, LookupTable};
 
-pub fn gen_code(
-    declarations: &str,
-    variables: &str,
-    lookup_table: &LookupTable,
-    handler_funcs: &Vec<String>,
-) -> String {
-    let lookup_table_json = format!("r#\"{}\"#", serde_json::to_string(lookup_table).unwrap());
-    // 生成handler_funcs
-    let mut handler_funcs_str = String::new();
-    for each in handler_funcs {
-        handler_funcs_str.push_str(&format!("\t\thandler_funcs.push(Box::new({}));\n", each));
+pub fn gen_code(config: &Config, lookup_table: &LookupTable) -> String {
+    let code = if config.options.no_std {
+        gen_no_std_code(config, lookup_table)
+    } else {
+        gen_std_code(config, lookup_table)
+    };
+
+    // %option name, 所有生成的代码放入同名的mod中
+    // name通常是lexer的类型名, 如JsonLexer
+    match &config.options.name {
+        Some(name) => format!(
+            "#[allow(non_snake_case)]\npub mod {} {{\n{}\n}}\n",
+            name, code
+        ),
+        None => code,
     }
+}
+
+/// gen_std_code
+/// a lexer over &str or &[u8], io::Read streams and pushed inputs
+fn gen_std_code(config: &Config, lookup_table: &LookupTable) -> String {
+    // bytes模式下handler接收&[u8]
+    let (text_type, text_conversion, unmatched_text) = if config.options.bytes {
+        ("[u8]", "", "unmatched.escape_ascii()")
+    } else {
+        (
+            "str",
+            "\n            let match_str = std::str::from_utf8(match_str)\n                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;",
+            "String::from_utf8_lossy(unmatched)",
+        )
+    };
+    // 没有规则匹配的输入
+    let (unmatched_len, char_len) = if config.options.unicode {
+        ("buffer.char_len()?", STD_CHAR_LEN)
+    } else {
+        ("1", "")
+    };
+    const SKIP: &str = "
+                buffer.pos += unmatched_len;
+                buffer.consume();
+                continue;";
+    const UNMATCHED: &str =
+        "\n                let unmatched = &buffer.data[buffer.pos..buffer.pos + unmatched_len];";
+    let unmatched_policy = match config.options.error {
+        ErrorPolicy::Print => format!(
+            "{}\n                println!(\"unknown error:{{}}\", {});{}",
+            UNMATCHED, unmatched_text, SKIP
+        ),
+        ErrorPolicy::Skip => SKIP.to_string(),
+        ErrorPolicy::Fail => format!(
+            "{}\n                return Err(io::Error::new(\n                    io::ErrorKind::InvalidData,\n                    format!(\"no rule matches \\\"{{}}\\\" at offset {{}}\", {}, buffer.offset + buffer.input_len()),\n                ));",
+            UNMATCHED, unmatched_text
+        ),
+    };
+    let first_line = first_line(config);
+    let count_lines = gen_count_lines(
+        config,
+        "(index, &byte) in self.data[self.start..self.pos].iter().enumerate()",
+        "
+            // unput的字节不计入行列
+            if self.is_unput(self.start + index) {
+                continue;
+            }",
+    );
+
+    let declarations = &config.declarations;
+    let variables = &config.variables;
+    let tables = gen_tables(config, lookup_table);
+    let conditions_str = gen_conditions(config);
+    let lexer = gen_lexer(config, text_type);
+
+    // 声明了%token时, 生成token类型, lex返回所有token
+    let tokens_enabled = !config.tokens.is_empty();
+    let token_types = gen_token_types(config);
+    // token借用输入, 流式输入的token则拥有自己的文本
+    let output_type = if tokens_enabled {
+        "Vec<Token<'input>>"
+    } else {
+        "()"
+    };
+    // error=fail时没有匹配的输入也是错误, lex返回它而不是panic
+    const UNWRAP: &str = "\n            .unwrap_or_else(|err| panic!(\"{}\", err))";
+    let (lex_ret, lex_unwrap) = match (config.options.error, tokens_enabled) {
+        (ErrorPolicy::Fail, _) => (format!(" -> io::Result<{}>", output_type), ""),
+        (_, true) => (format!(" -> {}", output_type), UNWRAP),
+        (_, false) => (String::new(), UNWRAP),
+    };
+    let (reader_output_type, reader_into_owned) = if tokens_enabled {
+        (
+            "Vec<Token<'static>>",
+            "\n            .map(|tokens| tokens.into_iter().map(Token::into_owned).collect())",
+        )
+    } else {
+        ("()", "")
+    };
+    let (token_ctx_field, token_ctx_init, token_ctx_methods) = if tokens_enabled {
+        (
+            "\n    emitted: Vec<TokenKind>,",
+            "\n            emitted: Vec::new(),",
+            TOKEN_CTX_METHODS,
+        )
+    } else {
+        ("", "", "")
+    };
+    // 声明了%token时, lex_buffer_with把每个token传给on_token, 其余的lex由它实现
+    let (lex_core, lex_core_doc, lex_core_param, token_lexer_methods, token_rlex_methods) =
+        if tokens_enabled {
+            (
+                "lex_buffer_with",
+                "\n    /// each token is passed to on_token as soon as it is lexed",
+                "\n        on_token: &mut dyn FnMut(Token<'input>),",
+                TOKEN_LEXER_METHODS,
+                TOKEN_RLEX_METHODS,
+            )
+        } else {
+            ("lex_buffer", "", "", "", "")
+        };
+    let (tokens_collect, reject_clear, eof_reject_clear) = if tokens_enabled {
+        (
+            "\n            for kind in ctx.emitted.drain(..) {\n                on_token(buffer.token(kind));\n            }",
+            "\n                    ctx.emitted.clear();",
+            "\n                        ctx.emitted.clear();",
+        )
+    } else {
+        ("", "", "")
+    };
 
     format!(
         r#"
-use serde::{{Deserialize, Serialize}};
-use std::collections::{{HashMap, HashSet}};
+use std::borrow::Cow;
+use std::io::{{self, Read}};
+use std::ops::{{Deref, DerefMut}};
 
 // declarations
 // ======================
 {declarations}
 // ======================
+{tables}
+// start conditions
+// ======================
+{conditions_str}
+// ======================
+{token_types}
+pub struct Rlex {{
+    // variables
+    // ======================
+    {variables}
+    // ======================
+}}
+{lexer}
+/// RlexContext
+/// passed to every handler as its first argument
+/// derefs to Rlex, so handlers can read and mutate the variables
+pub struct RlexContext<'a, 'input> {{
+    rlex: &'a mut Rlex,
+    condition: usize,
+    // 当前文本的位置和长度
+    line: usize,
+    column: usize,
+    len: usize,
+    depth: usize,
+    // handler对输入的调整
+    less: Option<usize>,
+    more: bool,
+    unput: Vec<u8>,
+    inputs: Vec<RlexBuffer<'input>>,
+    reject: bool,{token_ctx_field}
+}}
+
+impl<'a, 'input> RlexContext<'a, 'input> {{
+    /// begin
+    /// switch to another start condition, e.g. `ctx.begin(STRING)`
+    /// takes effect from the next match
+    pub fn begin(&mut self, condition: usize) {{
+        self.condition = condition;
+    }}
+
+    /// the current start condition
+    pub fn condition(&self) -> usize {{
+        self.condition
+    }}
+
+    /// yyless
+    /// keep the first n bytes of the current text, the rest will be lexed again
+    pub fn yyless(&mut self, n: usize) {{
+        assert!(n <= self.len, "yyless({{}}) on a text of {{}} bytes", n, self.len);
+        self.less = Some(n);
+    }}
+
+    /// yymore
+    /// the next match is appended to the current text instead of replacing it
+    pub fn yymore(&mut self) {{
+        self.more = true;
+    }}
+
+    /// unput
+    /// push s back to the input, it is read before anything else
+    pub fn unput(&mut self, s: &{text_type}) {{
+        let bytes: &[u8] = s.as_ref();
+        self.unput.splice(0..0, bytes.iter().copied());
+    }}
 
-#[derive(Serialize, Deserialize)]
-pub struct LookupState {{
-    pub handlers: HashSet<usize>,
-    pub neighbors: HashMap<u8, usize>,
+    /// reject
+    /// decline the current match, the next rule that matched the same text
+    /// or a shorter one is run instead
+    /// other adjustments made by the rejecting handler are dropped
+    pub fn reject(&mut self) {{
+        self.reject = true;
+    }}
+
+    /// push_input
+    /// suspend the current input and lex buffer, e.g. for an included file
+    /// the current input is resumed when buffer runs out
+    pub fn push_input(&mut self, buffer: RlexBuffer<'input>) {{
+        self.inputs.push(buffer);
+    }}
+
+    /// the number of suspended inputs
+    pub fn input_depth(&self) -> usize {{
+        self.depth
+    }}
+
+    /// the line of the current text, starting from 1
+    pub fn line(&self) -> usize {{
+        self.line
+    }}
+
+    /// the column of the current text in characters, starting from 1
+    pub fn column(&self) -> usize {{
+        self.column
+    }}{token_ctx_methods}
 }}
 
-impl LookupState {{
-    pub fn new() -> Self {{
-        LookupState {{
-            handlers: HashSet::new(),
-            neighbors: HashMap::new(),
+impl<'a, 'input> Deref for RlexContext<'a, 'input> {{
+    type Target = Rlex;
+
+    fn deref(&self) -> &Self::Target {{
+        self.rlex
+    }}
+}}
+
+impl<'a, 'input> DerefMut for RlexContext<'a, 'input> {{
+    fn deref_mut(&mut self) -> &mut Self::Target {{
+        self.rlex
+    }}
+}}
+
+/// RlexBuffer
+/// the input of the lexer, a whole string or a refillable buffer over io::Read
+/// bytes from the start of the current match are kept until the match is done,
+/// so a match may span several refills
+/// each buffer keeps its own line and column
+pub struct RlexBuffer<'input> {{
+    data: Cow<'input, [u8]>,
+    // start: 当前文本的开始, yymore时保留之前的匹配
+    // pos: 下一次匹配的开始
+    start: usize,
+    pos: usize,
+    reader: Option<Box<dyn Read + 'input>>,
+    // start的行列, 以及在整个输入中的偏移
+    line: usize,
+    column: usize,
+    offset: usize,
+    // data中unput的字节范围, 不计入偏移
+    unputs: Vec<(usize, usize)>,
+}}
+
+impl<'input> RlexBuffer<'input> {{
+    /// new
+    /// lex a whole input in memory
+    pub fn new(s: &'input {text_type}) -> Self {{
+        RlexBuffer {{
+            data: Cow::Borrowed(s.as_ref()),
+            start: 0,
+            pos: 0,
+            reader: None,
+            line: {first_line},
+            column: {first_line},
+            offset: 0,
+            unputs: Vec::new(),
+        }}
+    }}
+
+    /// from_owned
+    /// lex an owned input, e.g. a string built in a handler
+    pub fn from_owned(data: impl Into<Vec<u8>>) -> RlexBuffer<'static> {{
+        RlexBuffer {{
+            data: Cow::Owned(data.into()),
+            start: 0,
+            pos: 0,
+            reader: None,
+            line: {first_line},
+            column: {first_line},
+            offset: 0,
+            unputs: Vec::new(),
+        }}
+    }}
+
+    /// from_reader
+    /// lex a stream, reading RLEX_READ_SIZE bytes at a time
+    pub fn from_reader<R: Read + 'input>(reader: R) -> Self {{
+        RlexBuffer {{
+            data: Cow::Owned(Vec::new()),
+            start: 0,
+            pos: 0,
+            reader: Some(Box::new(reader)),
+            line: {first_line},
+            column: {first_line},
+            offset: 0,
+            unputs: Vec::new(),
+        }}
+    }}
+
+    /// fill
+    /// drop the consumed bytes and read more
+    /// return false if there is nothing left to read
+    fn fill(&mut self) -> io::Result<bool> {{
+        if self.reader.is_none() {{
+            return Ok(false);
+        }}
+
+        self.data.to_mut().drain(..self.start);
+        self.shift(self.start);
+        let (data, reader) = (self.data.to_mut(), self.reader.as_mut().unwrap());
+
+        let len = data.len();
+        data.resize(len + RLEX_READ_SIZE, 0);
+        loop {{
+            match reader.read(&mut data[len..]) {{
+                Ok(0) => {{
+                    data.truncate(len);
+                    self.reader = None;
+                    return Ok(false);
+                }}
+                Ok(n) => {{
+                    data.truncate(len + n);
+                    return Ok(true);
+                }}
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {{
+                    data.truncate(len);
+                    return Err(err);
+                }}
+            }}
+        }}
+    }}
+
+    /// shift
+    /// the first n bytes were dropped from data
+    fn shift(&mut self, n: usize) {{
+        self.start -= n;
+        self.pos -= n;
+        for (from, to) in self.unputs.iter_mut() {{
+            *from = from.saturating_sub(n);
+            *to -= n;
+        }}
+    }}
+
+    /// the byte at pos + offset, refill if needed
+    fn get(&mut self, offset: usize) -> io::Result<Option<u8>> {{
+        while self.pos + offset >= self.data.len() {{
+            if !self.fill()? {{
+                return Ok(None);
+            }}
+        }}
+        Ok(Some(self.data[self.pos + offset]))
+    }}
+
+    /// the offset of the current text in the whole input
+    pub fn offset(&self) -> usize {{
+        self.offset
+    }}
+
+    /// whether the byte at index of data was unput
+    fn is_unput(&self, index: usize) -> bool {{
+        self.unputs
+            .iter()
+            .any(|&(from, to)| from <= index && index < to)
+    }}
+
+    /// the length of the input between start and pos, without the unput bytes
+    fn input_len(&self) -> usize {{
+        let unput_len: usize = self
+            .unputs
+            .iter()
+            .map(|&(from, to)| to.min(self.pos).saturating_sub(from.max(self.start)))
+            .sum();
+        self.pos - self.start - unput_len
+    }}
+
+    /// consume
+    /// the current text is done, the next one starts at pos
+    fn consume(&mut self) {{
+{count_lines}        self.offset += self.input_len();
+        self.start = self.pos;
+        let start = self.start;
+        self.unputs.retain(|&(_, to)| to > start);
+    }}
+
+    /// unput
+    /// insert bytes before pos
+    /// the rest of a whole input is then read like a stream,
+    /// so only the bytes from start are moved
+    fn unput(&mut self, bytes: &[u8]) {{
+        if self.reader.is_none() {{
+            let rest: Box<dyn Read + 'input> = match &mut self.data {{
+                Cow::Borrowed(data) => {{
+                    let data: &'input [u8] = data;
+                    self.data = Cow::Owned(data[self.start..self.pos].to_vec());
+                    Box::new(&data[self.pos..])
+                }}
+                Cow::Owned(data) => {{
+                    let rest = data.split_off(self.pos);
+                    data.drain(..self.start);
+                    Box::new(io::Cursor::new(rest))
+                }}
+            }};
+            self.reader = Some(rest);
+            self.shift(self.start);
+        }}
+
+        // 和pos所在的unput范围合并
+        let (pos, len) = (self.pos, bytes.len());
+        self.data.to_mut().splice(pos..pos, bytes.iter().copied());
+        let mut merged = false;
+        for (from, to) in self.unputs.iter_mut() {{
+            if *from >= pos {{
+                *from += len;
+                *to += len;
+            }} else if *to >= pos {{
+                *to += len;
+                merged = true;
+            }}
+        }}
+        if !merged {{
+            self.unputs.push((pos, pos + len));
+            self.unputs.sort_unstable();
+        }}
+    }}{char_len}
+}}
+
+const RLEX_READ_SIZE: usize = 8192;
+
+impl Rlex {{
+    /// lex
+    /// lex a whole input in memory with RLEX_LEXER
+    pub fn lex<'input>(&mut self, s: &'input {text_type}){lex_ret} {{
+        RLEX_LEXER.lex(self, s)
+    }}
+
+    /// lex_reader
+    /// lex a stream without loading it into memory with RLEX_LEXER
+    pub fn lex_reader<R: Read>(&mut self, reader: R) -> io::Result<{reader_output_type}> {{
+        RLEX_LEXER.lex_reader(self, reader)
+    }}
+
+    /// lex_buffer
+    /// lex buffer and every input pushed by the handlers with RLEX_LEXER
+    pub fn lex_buffer<'input>(&mut self, buffer: RlexBuffer<'input>) -> io::Result<{output_type}> {{
+        RLEX_LEXER.lex_buffer(self, buffer)
+    }}{token_rlex_methods}
+}}
+
+impl RlexLexer {{
+    /// lex
+    /// lex a whole input in memory, rlex holds the variables of this input
+    pub fn lex<'input>(&self, rlex: &mut Rlex, s: &'input {text_type}){lex_ret} {{
+        self.lex_buffer(rlex, RlexBuffer::new(s)){lex_unwrap}
+    }}
+
+    /// lex_reader
+    /// lex a stream without loading it into memory
+    pub fn lex_reader<R: Read>(&self, rlex: &mut Rlex, reader: R) -> io::Result<{reader_output_type}> {{
+        self.lex_buffer(rlex, RlexBuffer::from_reader(reader)){reader_into_owned}
+    }}{token_lexer_methods}
+
+    /// {lex_core}
+    /// lex buffer and every input pushed by the handlers{lex_core_doc}
+    /// the state of the input lives on the stack, so a lexer can lex many inputs at once
+    pub fn {lex_core}<'input>(
+        &self,
+        rlex: &mut Rlex,
+        mut buffer: RlexBuffer<'input>,{lex_core_param}
+    ) -> io::Result<()> {{
+        // 执行词法分析
+        let mut ctx = RlexContext {{
+            rlex,
+            condition: INITIAL,
+            line: {first_line},
+            column: {first_line},
+            len: 0,
+            depth: 0,
+            less: None,
+            more: false,
+            unput: Vec::new(),
+            inputs: Vec::new(),
+            reject: false,{token_ctx_init}
+        }};
+        // push_input挂起的输入
+        let mut stack: Vec<RlexBuffer<'input>> = Vec::new();
+        // 扫描过程中经过的可接受状态
+        let mut accepts: Vec<(usize, usize)> = Vec::new();
+
+        loop {{
+            // 输入结束, 执行当前start condition的<<EOF>> handler
+            if buffer.get(0)?.is_none() {{
+                if let Some(handler_id) = self.eof_handlers[ctx.condition] {{
+                    let match_str = &buffer.data[buffer.start..buffer.pos];{text_conversion}
+                    ctx.len = match_str.len();
+                    ctx.line = buffer.line;
+                    ctx.column = buffer.column;
+                    ctx.depth = stack.len();
+                    self.handlers[handler_id](&mut ctx, match_str);
+                    ctx.less = None;
+                    ctx.more = false;
+                    // 没有别的规则可以代替, reject的<<EOF>> handler的调整都丢弃
+                    if std::mem::take(&mut ctx.reject) {{
+                        ctx.unput.clear();
+                        ctx.inputs.clear();{eof_reject_clear}
+                    }}{tokens_collect}
+                    buffer.consume();
+
+                    // handler可以unput更多的输入, 或者切换到新的输入
+                    if !ctx.unput.is_empty() {{
+                        buffer.unput(&ctx.unput);
+                        ctx.unput.clear();
+                        continue;
+                    }}
+                    if let Some(next) = ctx.inputs.pop() {{
+                        stack.append(&mut ctx.inputs);
+                        buffer = next;
+                        continue;
+                    }}
+                }}
+
+                // 回到被挂起的输入, 否则结束
+                match stack.pop() {{
+                    Some(prev) => buffer = prev,
+                    None => break,
+                }}
+                continue;
+            }}
+
+            self.match_reg(&mut buffer, self.starts[ctx.condition], &mut accepts)?;
+
+            // 从最长的匹配开始, 依次执行每个handler, 直到有handler没有reject
+            let mut accepted = None;
+            'candidates: for &(match_len, state) in accepts.iter().rev() {{
+                for &handler_id in self.accepts[state].iter() {{
+                    // yymore之后, 文本从之前的匹配开始
+                    let match_str = &buffer.data[buffer.start..buffer.pos + match_len];{text_conversion}
+                    ctx.len = match_str.len();
+                    ctx.line = buffer.line;
+                    ctx.column = buffer.column;
+                    ctx.depth = stack.len();
+                    self.handlers[handler_id](&mut ctx, match_str);
+
+                    if !std::mem::take(&mut ctx.reject) {{
+                        accepted = Some(ctx.len);
+                        break 'candidates;
+                    }}
+                    ctx.less = None;
+                    ctx.more = false;
+                    ctx.unput.clear();
+                    ctx.inputs.clear();{reject_clear}
+                }}
+            }}
+
+            // 没有规则匹配
+            let Some(text_len) = accepted else {{
+                let unmatched_len = {unmatched_len};{unmatched_policy}
+            }};
+
+            // 处理handler对输入的调整
+            buffer.pos = buffer.start + ctx.less.take().unwrap_or(text_len);{tokens_collect}
+            if !std::mem::take(&mut ctx.more) {{
+                buffer.consume();
+            }}
+            if !ctx.unput.is_empty() {{
+                buffer.unput(&ctx.unput);
+                ctx.unput.clear();
+            }}
+
+            // 挂起当前输入, 最后push的输入最先执行
+            for next in ctx.inputs.drain(..) {{
+                stack.push(std::mem::replace(&mut buffer, next));
+            }}
         }}
+
+        Ok(())
     }}
 
-    pub fn insert_neighbor(&mut self, ch: u8, index: usize) {{
-        self.neighbors.insert(ch, index);
+    /// match_reg
+    /// run the dfa over the rest input as far as possible
+    /// every accepting state passed is pushed to accepts as (matched_len, state),
+    /// the last one is the longest match
+    fn match_reg(
+        &self,
+        buffer: &mut RlexBuffer,
+        start: usize,
+        accepts: &mut Vec<(usize, usize)>,
+    ) -> io::Result<()> {{
+        let mut state: usize = start;
+        let mut index = 0;
+        accepts.clear();
+
+        while let Some(each) = buffer.get(index)? {{
+            if let Some(next_state) = rlex_next(state, each) {{
+                state = next_state;
+                index += 1;
+
+                // 如果有handler, 则记录可接受状态
+                if !self.accepts[state].is_empty() {{
+                    accepts.push((index, state));
+                }}
+            }} else {{
+                break;
+            }}
+        }}
+
+        Ok(())
     }}
 }}
+    "#
+    )
+    .to_string()
+}
+
+/// with_context_param
+/// handlers receive (&mut RlexContext, &str), or &[u8] in bytes mode
+/// closures written with a single parameter, like `|s| {...}`, ignore the context
+fn with_context_param(handler: &str) -> String {
+    let handler = handler.trim();
+    let (prefix, closure) = match handler.strip_prefix("move") {
+        Some(rest) if rest.trim_start().starts_with('|') => ("move ", rest.trim_start()),
+        _ => ("", handler),
+    };
+
+    // 不是闭包字面量, 原样输出
+    if !closure.starts_with('|') {
+        return handler.to_string();
+    }
+
+    // `||` 没有参数
+    if let Some(body) = closure.strip_prefix("||") {
+        return format!("{}|_, _|{}", prefix, body);
+    }
+
+    // 找到参数列表的结束位置, 统计顶层的参数个数
+    let mut depth = 0;
+    let mut params = 1;
+    for c in closure.chars().skip(1) {
+        match c {
+            '(' | '[' | '<' => depth += 1,
+            ')' | ']' | '>' => depth -= 1,
+            ',' if depth == 0 => params += 1,
+            '|' if depth == 0 => {
+                if params == 1 {
+                    return format!("{}|_, {}", prefix, &closure[1..]);
+                }
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    handler.to_string()
+}
+
+const TOKEN_CTX_METHODS: &str = r#"
 
-#[derive(Serialize, Deserialize)]
-pub struct LookupTable {{
-    pub states: Vec<LookupState>,
+    /// emit
+    /// emit a token of kind with the current text
+    pub fn emit(&mut self, kind: TokenKind) {
+        self.emitted.push(kind);
+    }"#;
+
+const TOKEN_LEXER_METHODS: &str = r#"
+
+    /// lex_reader_with
+    /// lex a stream, passing each token to on_token as soon as it is lexed,
+    /// so memory does not grow with the input
+    pub fn lex_reader_with<R: Read>(
+        &self,
+        rlex: &mut Rlex,
+        reader: R,
+        mut on_token: impl FnMut(Token<'static>),
+    ) -> io::Result<()> {
+        self.lex_buffer_with(rlex, RlexBuffer::from_reader(reader), &mut |token| {
+            on_token(token.into_owned())
+        })
+    }
+
+    /// lex_buffer
+    /// lex buffer and every input pushed by the handlers, returning every token
+    pub fn lex_buffer<'input>(
+        &self,
+        rlex: &mut Rlex,
+        buffer: RlexBuffer<'input>,
+    ) -> io::Result<Vec<Token<'input>>> {
+        let mut tokens = Vec::new();
+        self.lex_buffer_with(rlex, buffer, &mut |token| tokens.push(token))?;
+        Ok(tokens)
+    }"#;
+
+const TOKEN_RLEX_METHODS: &str = r#"
+
+    /// lex_reader_with
+    /// lex a stream with RLEX_LEXER, passing each token to on_token as soon as it is lexed
+    pub fn lex_reader_with<R: Read>(
+        &mut self,
+        reader: R,
+        on_token: impl FnMut(Token<'static>),
+    ) -> io::Result<()> {
+        RLEX_LEXER.lex_reader_with(self, reader, on_token)
+    }
+
+    /// lex_buffer_with
+    /// lex buffer with RLEX_LEXER, passing each token to on_token as soon as it is lexed
+    pub fn lex_buffer_with<'input>(
+        &mut self,
+        buffer: RlexBuffer<'input>,
+        on_token: &mut dyn FnMut(Token<'input>),
+    ) -> io::Result<()> {
+        RLEX_LEXER.lex_buffer_with(self, buffer, on_token)
+    }"#;
+
+/// gen_lexer
+/// the handlers and RlexLexer, the compiled spec shared by every input
+/// handlers are plain functions, so RlexLexer is Send + Sync
+fn gen_lexer(config: &Config, text_type: &str) -> String {
+    let mut handlers_str = String::new();
+    for rule in config.rules.iter() {
+        // pattern => Kind 生成默认handler
+        let handler = match &rule.token {
+            Some(kind) => format!("|ctx, _| ctx.emit(TokenKind::{})", kind),
+            None => with_context_param(&rule.handler),
+        };
+        handlers_str.push_str(&format!("    {},\n", handler));
+    }
+
+    format!(
+        r#"
+/// RlexHandler
+/// handlers are plain functions, closures without captures coerce to it
+pub type RlexHandler = fn(&mut RlexContext<'_, '_>, &{text_type});
+
+// rules
+// ======================
+#[allow(unused_variables)]
+static RLEX_HANDLERS: [RlexHandler; {handler_count}] = [
+{handlers_str}];
+// ======================
+
+/// RlexLexer
+/// the compiled spec: tables and handlers, immutable and Send + Sync
+/// one lexer can lex any number of inputs at once, e.g. from several threads,
+/// the variables of each input live in its own Rlex
+#[derive(Clone, Copy)]
+pub struct RlexLexer {{
+    handlers: &'static [RlexHandler],
+    accepts: &'static [&'static [usize]],
+    starts: &'static [usize],
+    eof_handlers: &'static [Option<usize>],
 }}
 
-impl LookupTable {{
+pub static RLEX_LEXER: RlexLexer = RlexLexer {{
+    handlers: &RLEX_HANDLERS,
+    accepts: &RLEX_ACCEPTS,
+    starts: &RLEX_STARTS,
+    eof_handlers: &RLEX_EOF_HANDLERS,
+}};
+
+impl RlexLexer {{
+    /// new
+    /// a copy of RLEX_LEXER, the tables are shared
     pub fn new() -> Self {{
-        LookupTable {{ states: Vec::new() }}
+        RLEX_LEXER
+    }}
+}}
+
+impl Default for RlexLexer {{
+    fn default() -> Self {{
+        Self::new()
     }}
 }}
 
+// RlexLexer可以在线程间共享
+const _: () = {{
+    const fn assert_send_sync<T: Send + Sync>() {{}}
+    assert_send_sync::<RlexLexer>();
+}};
+"#,
+        handler_count = config.rules.len(),
+    )
+}
+
+/// gen_conditions
+/// a usize constant for each start condition
+fn gen_conditions(config: &Config) -> String {
+    let mut conditions_str = String::new();
+    for (index, condition) in config.conditions.iter().enumerate() {
+        conditions_str.push_str(&format!(
+            "#[allow(non_upper_case_globals)]\npub const {}: usize = {};\n",
+            condition.name, index
+        ));
+    }
+    conditions_str
+}
+
+/// gen_tables
+/// static tables of the dfa, shared by the std and no_std runtime
+/// rlex_next is generated by the backend
+fn gen_tables(config: &Config, lookup_table: &LookupTable) -> String {
+    let states = &lookup_table.states;
+
+    let next = match config.options.backend {
+        Backend::Table => gen_table_next(lookup_table),
+        Backend::Match => gen_match_next(lookup_table),
+    };
+
+    // 每个状态接受的handler, 按优先级排序
+    let mut accepts = String::new();
+    for state in states.iter() {
+        let mut handlers = state.handlers.iter().copied().collect::<Vec<_>>();
+        handlers.sort_unstable();
+        accepts.push_str(&format!("&{:?}, ", handlers));
+    }
+
+    // 每个start condition的<<EOF>> handler, 取第一个生效的规则
+    let eof_handlers = config
+        .conditions
+        .iter()
+        .map(|condition| {
+            config
+                .rules
+                .iter()
+                .position(|rule| rule.eof && rule.is_active_in(condition))
+        })
+        .collect::<Vec<_>>();
+
+    format!(
+        r#"
+// tables
+// ======================
+/// handlers accepted in each state, sorted by priority
+static RLEX_ACCEPTS: [&[usize]; {state_count}] = [{accepts}];
+/// start state of each start condition
+static RLEX_STARTS: [usize; {condition_count}] = {starts:?};
+/// <<EOF>> handler of each start condition
+static RLEX_EOF_HANDLERS: [Option<usize>; {condition_count}] = {eof_handlers:?};
+{next}// ======================
+"#,
+        state_count = states.len(),
+        condition_count = config.conditions.len(),
+        starts = lookup_table.starts,
+    )
+}
+
+/// gen_table_next
+/// transitions in static arrays
+/// bytes with the same transitions in every state share a class,
+/// RLEX_TRANSITIONS[state * RLEX_CLASS_COUNT + class] is the next state
+fn gen_table_next(lookup_table: &LookupTable) -> String {
+    let states = &lookup_table.states;
+
+    // 计算每个字节的class
+    let mut classes = [0usize; 256];
+    let mut columns: Vec<Vec<Option<usize>>> = Vec::new();
+    for byte in 0..=255u8 {
+        let column = states
+            .iter()
+            .map(|state| state.neighbors.get(&byte).copied())
+            .collect::<Vec<_>>();
+        classes[byte as usize] = match columns.iter().position(|each| *each == column) {
+            Some(class) => class,
+            None => {
+                columns.push(column);
+                columns.len() - 1
+            }
+        };
+    }
+
+    // 状态数较少时使用u16
+    let state_type = if states.len() < u16::MAX as usize {
+        "u16"
+    } else {
+        "u32"
+    };
+
+    let mut transitions = String::new();
+    for state in 0..states.len() {
+        for column in columns.iter() {
+            match column[state] {
+                Some(next) => transitions.push_str(&format!("{}, ", next)),
+                None => transitions.push_str("RLEX_DEAD, "),
+            }
+        }
+        transitions.push('\n');
+    }
+
+    format!(
+        r#"const RLEX_DEAD: {state_type} = {state_type}::MAX;
+const RLEX_CLASS_COUNT: usize = {class_count};
+static RLEX_CLASSES: [u8; 256] = {classes:?};
+static RLEX_TRANSITIONS: [{state_type}; {transition_count}] = [
+{transitions}];
+
+/// rlex_next
+/// the next state of state on byte
+#[inline]
+fn rlex_next(state: usize, byte: u8) -> Option<usize> {{
+    let next = RLEX_TRANSITIONS[state * RLEX_CLASS_COUNT + RLEX_CLASSES[byte as usize] as usize];
+    if next == RLEX_DEAD {{
+        None
+    }} else {{
+        Some(next as usize)
+    }}
+}}
+"#,
+        class_count = columns.len(),
+        transition_count = columns.len() * states.len(),
+    )
+}
+
+/// gen_match_next
+/// transitions in a match expression, consecutive bytes with the same next state share an arm
+fn gen_match_next(lookup_table: &LookupTable) -> String {
+    let mut arms = String::new();
+    for (state, vertex) in lookup_table.states.iter().enumerate() {
+        let mut neighbors = vertex.neighbors.iter().collect::<Vec<_>>();
+        neighbors.sort_unstable();
+
+        // 合并连续的字节
+        let mut ranges: Vec<(u8, u8, usize)> = Vec::new();
+        for (&byte, &next) in neighbors {
+            match ranges.last_mut() {
+                Some((_, to, last)) if *last == next && *to as usize + 1 == byte as usize => {
+                    *to = byte
+                }
+                _ => ranges.push((byte, byte, next)),
+            }
+        }
+
+        for (from, to, next) in ranges {
+            if from == to {
+                arms.push_str(&format!(
+                    "        ({}, {:#04x}) => Some({}),\n",
+                    state, from, next
+                ));
+            } else {
+                arms.push_str(&format!(
+                    "        ({}, {:#04x}..={:#04x}) => Some({}),\n",
+                    state, from, to, next
+                ));
+            }
+        }
+    }
+
+    format!(
+        r#"
+/// rlex_next
+/// the next state of state on byte
+#[inline]
+fn rlex_next(state: usize, byte: u8) -> Option<usize> {{
+    match (state, byte) {{
+{arms}        _ => None,
+    }}
+}}
+"#
+    )
+}
+
+/// gen_token_types
+/// TokenKind, Span and Token for the kinds declared by %token
+fn gen_token_types(config: &Config) -> String {
+    if config.tokens.is_empty() {
+        return String::new();
+    }
+
+    let mut kinds = String::new();
+    let mut names = String::new();
+    for kind in config.tokens.iter() {
+        kinds.push_str(&format!("    {},\n", kind));
+        names.push_str(&format!(
+            "            TokenKind::{} => \"{}\",\n",
+            kind, kind
+        ));
+    }
+
+    // no_std模式下token直接借用输入
+    let token = if config.options.no_std {
+        NO_STD_TOKEN.to_string()
+    } else {
+        gen_std_token(config)
+    };
+
+    format!(
+        r#"
+// tokens
+// ======================
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {{
+{kinds}}}
+
+impl core::fmt::Display for TokenKind {{
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {{
+        let name = match self {{
+{names}        }};
+        f.write_str(name)
+    }}
+}}
+
+/// Span
+/// start and end are byte offsets in the input, line and column are those of start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {{
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}}
+{token}// ======================
+"#
+    )
+}
+
+/// gen_std_token
+/// Token of the std runtime, borrowing the input when possible
+fn gen_std_token(config: &Config) -> String {
+    // bytes模式下token的文本是[u8]
+    let (text_type, text_borrowed, text_owned, text_display) = if config.options.bytes {
+        (
+            "[u8]",
+            "Cow::Borrowed(&data[self.start..self.pos])",
+            "Cow::Owned(data[self.start..self.pos].to_vec())",
+            "self.text.escape_ascii()",
+        )
+    } else {
+        (
+            "str",
+            "String::from_utf8_lossy(&data[self.start..self.pos])",
+            "Cow::Owned(String::from_utf8_lossy(&data[self.start..self.pos]).into_owned())",
+            "self.text",
+        )
+    };
+
+    format!(
+        r#"
+/// Token
+/// text borrows the input when lexing a whole input in memory,
+/// and is owned when the input is a stream or was changed by unput
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token<'input> {{
+    pub kind: TokenKind,
+    pub text: Cow<'input, {text_type}>,
+    pub span: Span,
+}}
+
+impl<'input> Token<'input> {{
+    /// into_owned
+    /// a token not borrowing the input
+    pub fn into_owned(self) -> Token<'static> {{
+        Token {{
+            kind: self.kind,
+            text: Cow::Owned(self.text.into_owned()),
+            span: self.span,
+        }}
+    }}
+}}
+
+impl<'input> std::fmt::Display for Token<'input> {{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {{
+        write!(f, "<{{}}, {{}}>", self.kind, {text_display})
+    }}
+}}
+
+impl<'input> RlexBuffer<'input> {{
+    /// token
+    /// a token of kind with the current text
+    fn token(&self, kind: TokenKind) -> Token<'input> {{
+        let text = match &self.data {{
+            Cow::Borrowed(data) => {{
+                let data: &'input [u8] = data;
+                {text_borrowed}
+            }}
+            Cow::Owned(data) => {text_owned},
+        }};
+
+        Token {{
+            kind,
+            text,
+            span: Span {{
+                start: self.offset,
+                end: self.offset + self.input_len(),
+                line: self.line,
+                column: self.column,
+            }},
+        }}
+    }}
+}}
+"#
+    )
+}
+
+const NO_STD_TOKEN: &str = r#"
+/// Token
+/// text borrows the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'input> {
+    pub kind: TokenKind,
+    pub text: &'input [u8],
+    pub span: Span,
+}
+
+impl<'input> core::fmt::Display for Token<'input> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "<{}, {}>", self.kind, self.text.escape_ascii())
+    }
+}
+
+/// the most tokens a handler can emit, they are kept until the handler is done
+const RLEX_MAX_EMITTED: usize = 16;
+"#;
+
+/// gen_no_std_code
+/// a lexer using only core, for `#![no_std]` crates
+/// the input is a whole &[u8], tokens are passed to a callback, nothing is allocated
+fn gen_no_std_code(config: &Config, lookup_table: &LookupTable) -> String {
+    let declarations = &config.declarations;
+    let variables = &config.variables;
+    let tables = gen_tables(config, lookup_table);
+    let conditions_str = gen_conditions(config);
+    let lexer = gen_lexer(config, "[u8]");
+    let first_line = first_line(config);
+    let count_lines = gen_count_lines(config, "&byte in &self.input[self.start..pos]", "");
+    // 没有规则匹配的输入
+    let unmatched_len = if config.options.unicode {
+        "rlex_char_len(&ctx.input[pos..])"
+    } else {
+        "1"
+    };
+    let char_len = if config.options.unicode {
+        NO_STD_CHAR_LEN
+    } else {
+        ""
+    };
+
+    // 声明了%token时, token通过on_token传出
+    let token_types = gen_token_types(config);
+    let (token_ctx_field, token_ctx_init, token_ctx_methods, lex_param, lex_arg) = if config
+        .tokens
+        .is_empty()
+    {
+        ("", "", "", "", "")
+    } else {
+        (
+                "\n    on_token: &'a mut dyn FnMut(Token<'input>),\n    emitted: [Option<TokenKind>; RLEX_MAX_EMITTED],\n    emitted_len: usize,",
+                "\n            on_token,\n            emitted: [None; RLEX_MAX_EMITTED],\n            emitted_len: 0,",
+                NO_STD_TOKEN_CTX_METHODS,
+                ",\n        on_token: &mut dyn FnMut(Token<'input>),",
+                ", on_token",
+            )
+    };
+    // handler没有reject时才传出emit的token, 和std一样
+    let (tokens_flush, reject_clear, eof_tokens) = if config.tokens.is_empty() {
+        ("", "", "\n                    ctx.reject = false;")
+    } else {
+        (
+            "\n            ctx.flush(pos);",
+            "\n                    ctx.emitted_len = 0;",
+            "
+                    // 没有别的规则可以代替, reject的<<EOF>> handler的token都丢弃
+                    if core::mem::take(&mut ctx.reject) {
+                        ctx.emitted_len = 0;
+                    }
+                    ctx.flush(pos);",
+        )
+    };
+
+    format!(
+        r#"
+// declarations
+// ======================
+{declarations}
+// ======================
+{tables}
+// start conditions
+// ======================
+{conditions_str}
+// ======================
+{token_types}
 pub struct Rlex {{
     // variables
     // ======================
     {variables}
     // ======================
 }}
+{lexer}
+/// RlexContext
+/// passed to every handler as its first argument
+/// derefs to Rlex, so handlers can read and mutate the variables
+pub struct RlexContext<'a, 'input> {{
+    rlex: &'a mut Rlex,
+    input: &'input [u8],
+    condition: usize,
+    // 当前文本的位置和长度
+    start: usize,
+    line: usize,
+    column: usize,
+    len: usize,
+    // handler对输入的调整
+    less: Option<usize>,
+    more: bool,
+    reject: bool,{token_ctx_field}
+}}
+
+impl<'a, 'input> RlexContext<'a, 'input> {{
+    /// begin
+    /// switch to another start condition, e.g. `ctx.begin(STRING)`
+    /// takes effect from the next match
+    pub fn begin(&mut self, condition: usize) {{
+        self.condition = condition;
+    }}
+
+    /// the current start condition
+    pub fn condition(&self) -> usize {{
+        self.condition
+    }}
+
+    /// yyless
+    /// keep the first n bytes of the current text, the rest will be lexed again
+    pub fn yyless(&mut self, n: usize) {{
+        assert!(n <= self.len, "yyless({{}}) on a text of {{}} bytes", n, self.len);
+        self.less = Some(n);
+    }}
+
+    /// yymore
+    /// the next match is appended to the current text instead of replacing it
+    pub fn yymore(&mut self) {{
+        self.more = true;
+    }}
+
+    /// reject
+    /// decline the current match, the next rule that matched the same text
+    /// or a shorter one is run instead
+    /// other adjustments made by the rejecting handler are dropped
+    pub fn reject(&mut self) {{
+        self.reject = true;
+    }}
+
+    /// the line of the current text, starting from 1
+    pub fn line(&self) -> usize {{
+        self.line
+    }}
+
+    /// the column of the current text in characters, starting from 1
+    pub fn column(&self) -> usize {{
+        self.column
+    }}
+
+    /// the offset of the current text in the input
+    pub fn offset(&self) -> usize {{
+        self.start
+    }}{token_ctx_methods}
+
+    /// consume
+    /// the current text is done, the next one starts at pos
+    fn consume(&mut self, pos: usize) {{
+{count_lines}        self.start = pos;
+    }}
+}}
+
+impl<'a, 'input> core::ops::Deref for RlexContext<'a, 'input> {{
+    type Target = Rlex;
+
+    fn deref(&self) -> &Self::Target {{
+        self.rlex
+    }}
+}}
+
+impl<'a, 'input> core::ops::DerefMut for RlexContext<'a, 'input> {{
+    fn deref_mut(&mut self) -> &mut Self::Target {{
+        self.rlex
+    }}
+}}
 
 impl Rlex {{
-    pub fn lex(&self, s: &str) {{
-        // 词法分析
-        // 生成lookup_table
-        let lookup_table:LookupTable = serde_json::from_str({lookup_table_json}).unwrap();
-        let mut handler_funcs: Vec<Box<dyn Fn(&str) -> ()>> = Vec::new();
+    /// lex
+    /// lex a whole input in memory with RLEX_LEXER
+    /// return the number of bytes no rule matched, they are skipped
+    pub fn lex<'input>(
+        &mut self,
+        input: &'input [u8]{lex_param}
+    ) -> usize {{
+        RLEX_LEXER.lex(self, input{lex_arg})
+    }}
+}}
 
-        // rules
-        // ======================
-{handler_funcs_str}
-        // ======================
+impl RlexLexer {{
+    /// lex
+    /// lex a whole input in memory, rlex holds the variables of this input
+    /// return the number of bytes no rule matched, they are skipped
+    pub fn lex<'input>(
+        &self,
+        rlex: &mut Rlex,
+        input: &'input [u8]{lex_param}
+    ) -> usize {{
+        let mut ctx = RlexContext {{
+            rlex,
+            input,
+            condition: INITIAL,
+            start: 0,
+            line: {first_line},
+            column: {first_line},
+            len: 0,
+            less: None,
+            more: false,
+            reject: false,{token_ctx_init}
+        }};
+        // 下一次匹配的开始, yymore时ctx.start保留之前的匹配
+        let mut pos = 0;
+        let mut skipped = 0;
 
-        // 执行词法分析
-        let mut match_str: &str;
-        let mut res_str = s;
-        let mut handler_id: usize;
+        loop {{
+            // 输入结束, 执行当前start condition的<<EOF>> handler
+            if pos == ctx.input.len() {{
+                if let Some(handler_id) = self.eof_handlers[ctx.condition] {{
+                    let text = &ctx.input[ctx.start..pos];
+                    ctx.len = text.len();
+                    self.handlers[handler_id](&mut ctx, text);
+                    ctx.less = None;
+                    ctx.more = false;{eof_tokens}
+                    ctx.consume(pos);
+                }}
+                break;
+            }}
 
-        while res_str != "" {{
-            (match_str, res_str, handler_id) = Self::match_reg(res_str, &lookup_table);
+            // 从最长的匹配开始, 依次执行每个handler, 直到有handler没有reject
+            // reject时重新扫描更短的匹配, 不需要记录经过的可接受状态
+            let mut max_len = usize::MAX;
+            let mut accepted = None;
+            'candidates: while let Some((match_len, state)) =
+                self.match_reg(ctx.input, pos, self.starts[ctx.condition], max_len)
+            {{
+                for &handler_id in self.accepts[state].iter() {{
+                    let text = &ctx.input[ctx.start..pos + match_len];
+                    ctx.len = text.len();
+                    self.handlers[handler_id](&mut ctx, text);
 
-            // 如果匹配成功,则执行对应的handler
-            if match_str != "" {{
-                // 执行handler_func
-                handler_funcs.get(handler_id).unwrap()(match_str);
+                    if !core::mem::take(&mut ctx.reject) {{
+                        accepted = Some(ctx.len);
+                        break 'candidates;
+                    }}
+                    ctx.less = None;
+                    ctx.more = false;{reject_clear}
+                }}
+                max_len = match_len - 1;
             }}
 
-            // 未知错误
-            if match_str == "" {{
-                println!("unknown error:{{}}", &res_str[..1]);
-                res_str = &res_str[1..];
+            // 没有规则匹配, 跳过
+            let Some(text_len) = accepted else {{
+                let unmatched_len = {unmatched_len};
+                skipped += unmatched_len;
+                pos += unmatched_len;
+                ctx.consume(pos);
+                continue;
+            }};
+
+            // 处理handler对输入的调整
+            pos = ctx.start + ctx.less.take().unwrap_or(text_len);{tokens_flush}
+            if !core::mem::take(&mut ctx.more) {{
+                ctx.consume(pos);
             }}
         }}
+
+        skipped
     }}
 
     /// match_reg
-    /// for match a sub string that match a reg
-    /// return (matched_str, rest_str)
-    fn match_reg<'a>(s: &'a str, lookup: &LookupTable) -> (&'a str, &'a str, usize) {{
-        let mut state: usize = 0;
-        let mut last_match_index = 0;
-        let mut matched = false;
-        let mut handler_id = 0;
-
-        for (index, each) in s.as_bytes().iter().enumerate() {{
-            if let Some(next_state) = lookup.states[state].neighbors.get(each) {{
-                state = *next_state;
-
-                // 如果有handler, 则更新最后一个可接受状态
-                if !lookup.states[state].handlers.is_empty() {{
-                    last_match_index = index;
-                    matched = true;
-                    handler_id = *lookup.states[state].handlers.iter().min().unwrap();
-                }}
-            }} else {{
-                break;
+    /// run the dfa from pos over at most max_len bytes
+    /// return the longest match as (matched_len, state)
+    fn match_reg(
+        &self,
+        input: &[u8],
+        pos: usize,
+        start: usize,
+        max_len: usize,
+    ) -> Option<(usize, usize)> {{
+        let mut state: usize = start;
+        let mut res = None;
+
+        for (index, &each) in input[pos..].iter().take(max_len).enumerate() {{
+            match rlex_next(state, each) {{
+                Some(next_state) => state = next_state,
+                None => break,
             }}
-        }}
 
-        if !matched {{
-            return ("", s, handler_id);
+            // 如果有handler, 则记录可接受状态
+            if !self.accepts[state].is_empty() {{
+                res = Some((index + 1, state));
+            }}
         }}
-        (
-            &s[..last_match_index + 1],
-            &s[last_match_index + 1..],
-            handler_id,
-        )
+
+        res
     }}
 }}
-    "#
+{char_len}"#
     )
-    .to_string()
 }
+
+const NO_STD_TOKEN_CTX_METHODS: &str = r#"
+
+    /// emit
+    /// emit a token of kind with the current text
+    /// it is passed to on_token when the handler is done, unless the handler rejects
+    pub fn emit(&mut self, kind: TokenKind) {
+        assert!(
+            self.emitted_len < RLEX_MAX_EMITTED,
+            "a handler can emit at most {} tokens",
+            RLEX_MAX_EMITTED
+        );
+        self.emitted[self.emitted_len] = Some(kind);
+        self.emitted_len += 1;
+    }
+
+    /// flush
+    /// pass the emitted tokens to on_token, the text ends at end
+    fn flush(&mut self, end: usize) {
+        for index in 0..core::mem::take(&mut self.emitted_len) {
+            (self.on_token)(Token {
+                kind: self.emitted[index].unwrap(),
+                text: &self.input[self.start..end],
+                span: Span {
+                    start: self.start,
+                    end,
+                    line: self.line,
+                    column: self.column,
+                },
+            });
+        }
+    }"#;
+
+const NO_STD_CHAR_LEN: &str = r#"
+/// rlex_char_len
+/// the length of the utf-8 character at the start of input, 1 if it is not valid
+fn rlex_char_len(input: &[u8]) -> usize {
+    let len = match input[0] {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    };
+
+    let mut res = 1;
+    while res < len && matches!(input.get(res), Some(0x80..=0xBF)) {
+        res += 1;
+    }
+    res
+}
+"#;
+
+/// first_line
+/// the first line and column, 0 if lines are not tracked
+fn first_line(config: &Config) -> usize {
+    if config.options.lines {
+        1
+    } else {
+        0
+    }
+}
+
+/// gen_count_lines
+/// the loop updating line and column over the consumed bytes,
+/// bytes binds byte in the loop, skip may continue before a byte is counted
+/// columns count characters in unicode mode, bytes otherwise
+fn gen_count_lines(config: &Config, bytes: &str, skip: &str) -> String {
+    if !config.options.lines {
+        return String::new();
+    }
+
+    let column = if config.options.unicode {
+        "} else if byte & 0xC0 != 0x80 {"
+    } else {
+        "} else {"
+    };
+    format!(
+        "        for {bytes} {{{skip}
+            if byte == b'\\n' {{
+                self.line += 1;
+                self.column = 1;
+            {column}
+                self.column += 1;
+            }}
+        }}
+"
+    )
+}
+
+const STD_CHAR_LEN: &str = r#"
+
+    /// char_len
+    /// the length of the utf-8 character at pos, 1 if it is not valid
+    fn char_len(&mut self) -> io::Result<usize> {
+        let len = match self.get(0)? {
+            Some(0xC0..=0xDF) => 2,
+            Some(0xE0..=0xEF) => 3,
+            Some(0xF0..=0xF7) => 4,
+            _ => 1,
+        };
+
+        let mut res = 1;
+        while res < len && matches!(self.get(res)?, Some(0x80..=0xBF)) {
+            res += 1;
+        }
+        Ok(res)
+    }"#;
@@ -0,0 +1,170 @@
+use crate::{Config, Dfa, Example, LookupTable, Nfa, NfaBuilder, RegexExpr, RegexOptions};
+use std::{
+    collections::{HashSet, VecDeque},
+    error::Error,
+};
+
+/// build_dfa
+/// build the dfa of all rules in config, with one start state per start condition
+/// handler ids are the indexes of config.rules
+pub fn build_dfa(config: &Config) -> Result<Dfa, Box<dyn Error>> {
+    // case-insensitive时字母匹配大小写, 输入为字节时[^...]对所有字节取反
+    let options = RegexOptions {
+        caseless: config.options.case_insensitive,
+        bytes: config.options.bytes || config.options.no_std,
+    };
+    // <<EOF>>规则不参与匹配
+    let exprs = config
+        .rules
+        .iter()
+        .map(|rule| {
+            if rule.eof {
+                Ok(None)
+            } else {
+                Ok(Some(RegexExpr::from_ast(&rule.regex, &options)?))
+            }
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+    // build nfa, 每个start condition一个
+    let mut nfas = Vec::new();
+    for condition in config.conditions.iter() {
+        let mut nfa_builder = NfaBuilder::new();
+        for (handler_id, rule) in config.rules.iter().enumerate() {
+            if let (Some(expr), true) = (&exprs[handler_id], rule.is_active_in(condition)) {
+                nfa_builder.add_rule(expr, handler_id);
+            }
+        }
+        nfas.push(nfa_builder.build().unwrap_or_else(Nfa::empty));
+    }
+
+    let dfa = Dfa::build_with_conditions(&nfas);
+
+    // &str的输入, 匹配的文本必须是完整的utf-8字符
+    if !config.options.bytes && !config.options.no_std {
+        if let Some((rule, example)) = partial_utf8_match(config, &dfa.lookup_table) {
+            return Err(format!(
+                "parsing config error: rule `{}` can match part of a utf-8 character, e.g. {}, \
+use \"%option bytes\" to lex bytes",
+                config.describe_rule(rule),
+                example.describe(config)
+            )
+            .into());
+        }
+    }
+
+    Ok(dfa)
+}
+
+/// Utf8State
+/// where a text is in the utf-8 character being read
+/// remaining: continuation bytes still expected, each in lo..=hi
+/// inside: the text started inside a character, only without `%option unicode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Utf8State {
+    remaining: u8,
+    lo: u8,
+    hi: u8,
+    inside: bool,
+}
+
+impl Utf8State {
+    fn new(remaining: u8, lo: u8, hi: u8) -> Utf8State {
+        Utf8State {
+            remaining,
+            lo,
+            hi,
+            inside: false,
+        }
+    }
+
+    /// next
+    /// the state after byte c, None if valid utf-8 can't continue with c
+    fn next(self, c: u8) -> Option<Utf8State> {
+        let next = if self.remaining > 0 {
+            if !(self.lo..=self.hi).contains(&c) {
+                return None;
+            }
+            Utf8State::new(self.remaining - 1, 0x80, 0xBF)
+        } else {
+            match c {
+                0x00..=0x7F => Utf8State::new(0, 0x80, 0xBF),
+                0xC2..=0xDF => Utf8State::new(1, 0x80, 0xBF),
+                0xE0 => Utf8State::new(2, 0xA0, 0xBF),
+                0xED => Utf8State::new(2, 0x80, 0x9F),
+                0xE1..=0xEF => Utf8State::new(2, 0x80, 0xBF),
+                0xF0 => Utf8State::new(3, 0x90, 0xBF),
+                0xF1..=0xF3 => Utf8State::new(3, 0x80, 0xBF),
+                0xF4 => Utf8State::new(3, 0x80, 0x8F),
+                _ => return None,
+            }
+        };
+
+        Some(Utf8State {
+            inside: self.inside,
+            ..next
+        })
+    }
+
+    /// a text ending here is valid utf-8
+    fn is_complete(&self) -> bool {
+        self.remaining == 0 && !self.inside
+    }
+}
+
+/// partial_utf8_match
+/// a rule and an input of valid utf-8 it matches without matching whole characters,
+/// e.g. `\xC3` matches the first byte of "é"
+/// without `%option unicode`, unmatched bytes are skipped one at a time,
+/// so a text may also start inside a character
+fn partial_utf8_match(config: &Config, table: &LookupTable) -> Option<(usize, Example)> {
+    let mut visited: HashSet<(usize, Utf8State)> = HashSet::new();
+    let mut queue: VecDeque<(usize, Utf8State, Example)> = VecDeque::new();
+    for (condition, start) in table.starts.iter().enumerate() {
+        let example = Example {
+            text: Vec::new(),
+            condition,
+        };
+        queue.push_back((*start, Utf8State::new(0, 0x80, 0xBF), example.clone()));
+        if !config.options.unicode {
+            for remaining in 1..=3 {
+                let inside = Utf8State {
+                    inside: true,
+                    ..Utf8State::new(remaining, 0x80, 0xBF)
+                };
+                queue.push_back((*start, inside, example.clone()));
+            }
+        }
+    }
+
+    while let Some((state, utf8, example)) = queue.pop_front() {
+        if !visited.insert((state, utf8)) {
+            continue;
+        }
+        let handlers = &table.states[state].handlers;
+        // 空串不会被匹配
+        if !utf8.is_complete() && !handlers.is_empty() && !example.text.is_empty() {
+            return Some((*handlers.iter().min().unwrap(), example));
+        }
+
+        let mut neighbors: Vec<(u8, usize)> = table.states[state]
+            .neighbors
+            .iter()
+            .map(|(c, next)| (*c, *next))
+            .collect();
+        neighbors.sort_unstable();
+        for (c, next) in neighbors {
+            if let Some(next_utf8) = utf8.next(c) {
+                let mut text = example.text.clone();
+                text.push(c);
+                let next_example = Example {
+                    text,
+                    condition: example.condition,
+                };
+                queue.push_back((next, next_utf8, next_example));
+            }
+        }
+    }
+
+    None
+}
@@ -1,161 +1,1085 @@
-use regex::Regex;
-use std::{collections::HashMap, error::Error, fs::File, io::Read};
-
-const RLEX_FILE_SAMPLE: &str = r#"
-%{
-    // declarations
-    // declare your structs here
-    pub struct Foo {
-        x: i32,
-        y: i32,
-    }
-
-    pub struct Bar {
-        x: i32,
-        y: i32,
-    }
-%}
-    // definitions
-    // define your regex variables here
-    number = "[0-9]*"
-    idenfitier = "[A-Za-z][A-Za-z0-9]*"
-    error = "( |;|?|,|!|=)*"
-%%
-    // rules
-    // define your rules here
-    // regex -> handler_func
-    {number} -> |s|{
-        println!("number: {}", s);
-    } ;;
-    {idenfitier} -> |s|{
-        println!("idenfitier: {}", s);
-    } ;;
-    {error} -> |s|{
-        println!("error: {}", s);
-    } ;;
-%%
-    // variables
-    // define your variables here
-    pub a: i32,
-    pub b: i64, 
-"#;
+use crate::{
+    parser::{
+        self, Item, ParseError, Source, Span, SpecDefinition, SpecDirective, SpecFile, SpecRule,
+    },
+    regex_expr::{RegexAst, RegexDefinition, RegexError},
+    spec_document::SpecDocument,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    slice,
+};
+
+pub const INITIAL_CONDITION: &str = "INITIAL";
 
 pub struct Config {
     pub declarations: String,
-    pub rules: Vec<(String, String)>,
+    /// start conditions, conditions[0] is always INITIAL
+    pub conditions: Vec<Condition>,
+    /// token kinds declared by `%token`
+    pub tokens: Vec<String>,
+    /// definitions as written, in file order
+    pub definitions: Vec<Definition>,
+    pub rules: Vec<Rule>,
     pub variables: String,
+    pub options: Options,
+}
+
+impl Config {
+    /// describe_rule
+    /// the pattern of a rule with its `<A,B>` prefix, for messages
+    pub fn describe_rule(&self, rule: usize) -> String {
+        let rule = &self.rules[rule];
+        if rule.conditions.is_empty() {
+            return rule.pattern.clone();
+        }
+
+        format!("<{}>{}", rule.conditions.join(","), rule.pattern)
+    }
+}
+
+/// Options
+/// generator settings, set by `%option` in the definitions section
+pub struct Options {
+    /// handlers receive &[u8] instead of &str
+    pub bytes: bool,
+    /// generate code using only core, implies bytes
+    pub no_std: bool,
+    /// `%option name=NAME`, generate the lexer inside `pub mod NAME`
+    pub name: Option<String>,
+    /// ascii letters in patterns match both cases
+    pub case_insensitive: bool,
+    /// how the dfa is generated
+    pub backend: Backend,
+    /// the input is utf-8 text: columns count characters,
+    /// unmatched input is skipped a character at a time
+    pub unicode: bool,
+    /// what to do with input no rule matches
+    pub error: ErrorPolicy,
+    /// track the line and column of each text
+    pub lines: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            bytes: false,
+            no_std: false,
+            name: None,
+            case_insensitive: false,
+            backend: Backend::Table,
+            unicode: true,
+            error: ErrorPolicy::Print,
+            lines: true,
+        }
+    }
+}
+
+impl Options {
+    /// validate
+    /// check options that can't be used together
+    /// called again after options are changed, e.g. by command line flags
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.no_std && self.error == ErrorPolicy::Fail {
+            return Err(
+                "parsing config error: option \"error=fail\" is not supported with \"no-std\""
+                    .into(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Backend
+/// table: transitions in static arrays, indexed by byte class
+/// match: transitions in a match expression, no tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Table,
+    Match,
+}
+
+/// ErrorPolicy
+/// print: print the unmatched input and skip it
+/// skip: skip the unmatched input silently
+/// fail: stop lexing and return an error
+/// with no-std, unmatched input is always skipped and counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    Print,
+    Skip,
+    Fail,
+}
+
+/// the options accepted by `%option`, for error messages
+const OPTION_NAMES: &str =
+    "name=NAME, prefix=NAME, case-insensitive, caseless, backend=table|match, \
+unicode, error=print|skip|fail, lines, bytes, no-std, noX turns off a flag";
+
+/// Condition
+/// a start condition declared by `%s` (inclusive) or `%x` (exclusive)
+pub struct Condition {
+    pub name: String,
+    pub exclusive: bool,
+}
+
+/// Definition
+/// `name = pattern` or `name(param, ...) = pattern` in the definitions section,
+/// pattern is kept as written
+/// included: the definition comes from a file included by `%include`
+pub struct Definition {
+    pub name: String,
+    pub params: Vec<String>,
+    pub pattern: String,
+    pub included: bool,
+}
+
+pub const EOF_PATTERN: &str = "<<EOF>>";
+
+/// Rule
+/// pattern: the regex as written, `<<EOF>>` for eof rules
+/// regex: the parsed pattern with definitions replaced, Empty for `<<EOF>>` rules
+/// conditions: the `<A,B>` prefix, empty if the rule has no prefix
+/// eof: the rule is run when the input runs out instead of matching
+/// token: `pattern => Kind` rules emit a token of Kind instead of running a handler
+pub struct Rule {
+    pub pattern: String,
+    pub regex: RegexAst,
+    pub handler: String,
+    pub conditions: Vec<String>,
+    pub eof: bool,
+    pub token: Option<String>,
+}
+
+impl Rule {
+    /// is_active_in
+    /// rules without prefix are active in INITIAL and every inclusive condition
+    /// `<*>` is active in every condition
+    pub fn is_active_in(&self, condition: &Condition) -> bool {
+        if self.conditions.is_empty() {
+            return !condition.exclusive;
+        }
+
+        self.conditions
+            .iter()
+            .any(|each| each == "*" || *each == condition.name)
+    }
 }
 
+/// parse_config
+/// parse a .rlex file and the files it includes,
+/// `.toml` and `.json` files are read as a SpecDocument
 pub fn parse_config(path: &str) -> Result<Config, Box<dyn Error>> {
     // 读取文件
-    let (declarations, definitions, rules, variables) = read_config_file(path)?;
+    let mut f = File::open(path)?;
+    let mut text = String::new();
+    f.read_to_string(&mut text)?;
+
+    match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => return SpecDocument::from_toml(path, &text)?.to_config(path),
+        Some("json") => return SpecDocument::from_json(path, &text)?.to_config(path),
+        _ => {}
+    }
+
+    // 被include的文件在include它的文件之前
+    let mut files = Vec::new();
+    load_file(path.to_string(), text, &mut Vec::new(), &mut files)?;
+
+    let main = files.len() - 1;
+    let mut parts = SpecParts::default();
+    for (index, file) in files.iter().enumerate() {
+        let source = file.source();
+        parts.declarations.push_str(&file.spec.declarations);
+        parts
+            .directives
+            .extend(file.spec.directives.iter().map(|each| (source, each)));
+        parts.definitions.extend(
+            file.spec
+                .definitions
+                .iter()
+                .map(|each| (source, each, index != main)),
+        );
+        parts
+            .rules
+            .extend(file.spec.rules.iter().map(|each| (source, each)));
+        parts.variables.push_str(&file.spec.variables);
+    }
+
+    build_config(parts)
+}
+
+/// SpecParts
+/// the sections of a spec, each piece with the source its spans point into
+/// definitions: whether each one is included from another file
+#[derive(Default)]
+pub(crate) struct SpecParts<'a> {
+    pub declarations: String,
+    pub directives: Vec<(Source<'a>, &'a SpecDirective)>,
+    pub definitions: Vec<(Source<'a>, &'a SpecDefinition, bool)>,
+    pub rules: Vec<(Source<'a>, &'a SpecRule)>,
+    pub variables: String,
+}
+
+/// build_config
+/// check the parts of a spec and build its config
+pub(crate) fn build_config(parts: SpecParts) -> Result<Config, Box<dyn Error>> {
+    // 将start conditions, tokens和options提取出来
+    let mut directives = Directives::new();
+    for (source, directive) in parts.directives.iter() {
+        parse_directives(source, slice::from_ref(*directive), &mut directives)?;
+    }
+    let Directives {
+        conditions,
+        tokens,
+        options,
+    } = directives;
+    options.validate()?;
     // 将definitions中的变量提取出来
-    let definitions = parse_definations(&definitions)?;
+    let spec_definitions: Vec<(Source, &SpecDefinition)> = parts
+        .definitions
+        .iter()
+        .map(|(source, each, _)| (*source, *each))
+        .collect();
+    let resolved = parse_definations(&spec_definitions)?;
     // 将rules中的变量提取出来
-    let rules = parse_rules(&rules, &definitions)?;
+    let mut rules = Vec::new();
+    for (source, rule) in parts.rules.iter() {
+        rules.extend(parse_rules(
+            source,
+            slice::from_ref(*rule),
+            &resolved,
+            &conditions,
+            &tokens,
+        )?);
+    }
+
+    let definitions = parts
+        .definitions
+        .iter()
+        .map(|(_, each, included)| Definition {
+            name: each.name.text.clone(),
+            params: each.params.iter().map(|param| param.text.clone()).collect(),
+            pattern: each.pattern.text.clone(),
+            included: *included,
+        })
+        .collect();
 
     Ok(Config {
-        declarations,
+        declarations: parts.declarations,
+        conditions,
+        tokens,
+        definitions,
         rules,
-        variables,
+        variables: parts.variables,
+        options,
     })
 }
 
-fn read_config_file(path: &str) -> Result<(String, String, String, String), Box<dyn Error>> {
-    let mut f = File::open(path)?;
-
-    let mut buf = String::new();
-    f.read_to_string(&mut buf)?;
+/// SpecSource
+/// a parsed .rlex file, with the text its spans point into
+struct SpecSource {
+    path: String,
+    canonical: PathBuf,
+    text: String,
+    spec: SpecFile,
+}
 
-    // 读取各项配置
-    if let Some(captures) = Regex::new(r"(?s)%\{(.+?)%\}(.*?)%%(.*?)%%(.+)")
-        .unwrap()
-        .captures(&buf)
-    {
-        let declarations = captures[1].to_string();
-        let definitions = captures[2].to_string();
-        let rules = captures[3].to_string();
-        let variables = captures[4].to_string();
+impl SpecSource {
+    fn source(&self) -> Source<'_> {
+        Source {
+            path: &self.path,
+            text: &self.text,
+        }
+    }
+}
 
-        Ok((declarations, definitions, rules, variables))
+/// load_file
+/// parse a .rlex file and the files it includes, paths are relative to the including file
+/// files are pushed after the files they include, a file included twice is loaded once
+/// chain is the files being loaded, including one of them again is a cycle
+fn load_file(
+    path: String,
+    text: String,
+    chain: &mut Vec<(PathBuf, String)>,
+    files: &mut Vec<SpecSource>,
+) -> Result<(), Box<dyn Error>> {
+    let canonical = fs::canonicalize(&path)?;
+    let source = Source {
+        path: &path,
+        text: &text,
+    };
+    let spec = if chain.is_empty() {
+        parser::parse(&source)?
     } else {
-        Err(format!(
-            "parsing config error: config file format error\n*.flex file sample:\n{}\n",
-            RLEX_FILE_SAMPLE
-        )
-        .into())
+        parser::parse_include(&source)?
+    };
+
+    chain.push((canonical.clone(), path.clone()));
+    for include in spec.includes.iter() {
+        let include_path = Path::new(&path)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&include.text);
+        let include_name = include_path.to_string_lossy().into_owned();
+        let read_error = |err: io::Error| {
+            source.error(
+                include.span,
+                format!("can't read \"{}\": {}", include_name, err),
+            )
+        };
+
+        let include_canonical = fs::canonicalize(&include_path).map_err(read_error)?;
+        if let Some(index) = chain
+            .iter()
+            .position(|(each, _)| *each == include_canonical)
+        {
+            let names: Vec<&str> = chain[index..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .collect();
+            return Err(source
+                .error(
+                    include.span,
+                    format!("include cycle: {} -> {}", names.join(" -> "), include_name),
+                )
+                .into());
+        }
+        if files.iter().any(|file| file.canonical == include_canonical) {
+            continue;
+        }
+
+        let include_text = fs::read_to_string(&include_path).map_err(read_error)?;
+        load_file(include_name, include_text, chain, files)?;
+    }
+    chain.pop();
+
+    files.push(SpecSource {
+        path,
+        canonical,
+        text,
+        spec,
+    });
+
+    Ok(())
+}
+
+/// Directives
+/// the settings declared by `%` lines of the definitions section
+struct Directives {
+    conditions: Vec<Condition>,
+    tokens: Vec<String>,
+    options: Options,
+}
+
+impl Directives {
+    fn new() -> Self {
+        Directives {
+            conditions: vec![Condition {
+                name: INITIAL_CONDITION.to_string(),
+                exclusive: false,
+            }],
+            tokens: Vec::new(),
+            options: Options::default(),
+        }
+    }
+}
+
+/// parse_directives
+/// `%s NAME...`, `%x NAME...`, `%token NAME...` and `%option ...`
+/// the settings are added to res, which is shared by a file and the files it includes
+fn parse_directives(
+    source: &Source,
+    directives: &[SpecDirective],
+    res: &mut Directives,
+) -> Result<(), ParseError> {
+    let Directives {
+        conditions,
+        tokens,
+        options,
+    } = res;
+
+    for directive in directives {
+        for arg in directive.args.iter() {
+            let res = match directive.name.text.as_str() {
+                "%s" | "%x" => {
+                    let declared = conditions.iter().any(|each| each.name == arg.text);
+                    check_name("start condition", &arg.text, declared).map(|_| {
+                        conditions.push(Condition {
+                            name: arg.text.clone(),
+                            exclusive: directive.name.text == "%x",
+                        })
+                    })
+                }
+                "%token" => {
+                    let declared = tokens.contains(&arg.text);
+                    check_name("token", &arg.text, declared).map(|_| tokens.push(arg.text.clone()))
+                }
+                _ => parse_option(&arg.text, options),
+            };
+            res.map_err(|message| source.error(arg.span, message))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// parse_option
+/// `key=value` or a flag, flags are turned off by a `no` prefix as in flex
+/// values may be quoted
+fn parse_option(option: &str, options: &mut Options) -> Result<(), String> {
+    let (key, value) = match option.split_once('=') {
+        Some((key, value)) => (key, Some(value.trim_matches('"'))),
+        None => (option, None),
+    };
+
+    // 带值的选项
+    match (key, value) {
+        ("name" | "prefix", Some(value)) => {
+            check_name("lexer", value, options.name.is_some())?;
+            options.name = Some(value.to_string());
+            return Ok(());
+        }
+        ("backend", Some(value)) => {
+            options.backend = match value {
+                "table" => Backend::Table,
+                "match" => Backend::Match,
+                _ => return Err(invalid_option_value(key, value, "table or match")),
+            };
+            return Ok(());
+        }
+        ("error", Some(value)) => {
+            options.error = match value {
+                "print" => ErrorPolicy::Print,
+                "skip" => ErrorPolicy::Skip,
+                "fail" => ErrorPolicy::Fail,
+                _ => return Err(invalid_option_value(key, value, "print, skip or fail")),
+            };
+            return Ok(());
+        }
+        ("name" | "prefix" | "backend" | "error", None) => {
+            return Err(format!("option \"{}\" needs a value", key));
+        }
+        (_, Some(_)) if flag(options, key).is_some() => {
+            return Err(format!("option \"{}\" takes no value", key));
+        }
+        _ => {}
+    }
+
+    // 开关选项, no前缀表示关闭
+    let (key, on) = match flag(options, key) {
+        Some(_) => (key, true),
+        None => match key.strip_prefix("no") {
+            Some(rest) if flag(options, rest).is_some() => (rest, false),
+            _ => {
+                return Err(format!(
+                    "unknown option \"{}\", options are: {}",
+                    key, OPTION_NAMES
+                ))
+            }
+        },
+    };
+    *flag(options, key).unwrap() = on;
+
+    Ok(())
+}
+
+/// flag
+/// the field of an on/off option
+fn flag<'a>(options: &'a mut Options, key: &str) -> Option<&'a mut bool> {
+    match key {
+        "case-insensitive" | "caseless" => Some(&mut options.case_insensitive),
+        "unicode" => Some(&mut options.unicode),
+        "lines" => Some(&mut options.lines),
+        "bytes" => Some(&mut options.bytes),
+        "no-std" => Some(&mut options.no_std),
+        _ => None,
     }
 }
 
-fn parse_definations(definitions: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
-    let mut raw_definations: Vec<(String, String)> = Vec::new();
+fn invalid_option_value(key: &str, value: &str, expected: &str) -> String {
+    format!(
+        "invalid value \"{}\" for option \"{}\", expected {}",
+        value, key, expected
+    )
+}
 
-    // parse definitions to hash_map
-    for captures in Regex::new(r#"([\s\S]*?)=([\s\S]*?)\n"#)
-        .unwrap()
-        .captures_iter(&definitions)
+/// check_name
+/// names of start conditions and tokens are used as rust identifiers
+fn check_name(kind: &str, name: &str, declared: bool) -> Result<(), String> {
+    if name.starts_with(|c: char| c.is_ascii_digit())
+        || !name.chars().all(|c| c.is_alphanumeric() || c == '_')
     {
-        let key = captures[1].trim().to_string();
-        let value = captures[2].trim().to_string();
-        raw_definations.push((key, value));
+        return Err(format!("invalid {} name \"{}\"", kind, name));
+    }
+    if declared {
+        return Err(format!("{} \"{}\" declared twice", kind, name));
+    }
+
+    Ok(())
+}
+
+/// parse_definations
+/// definitions may reference each other in any order,
+/// each one is resolved after the definitions it references
+/// definitions come from several files when files are included
+fn parse_definations(
+    definitions: &[(Source, &SpecDefinition)],
+) -> Result<HashMap<String, RegexDefinition>, ParseError> {
+    // 先解析所有definition, 不替换变量
+    let mut parsed: HashMap<&str, (Source, &SpecDefinition, RegexAst)> = HashMap::new();
+    for (source, definition) in definitions {
+        let name = definition.name.text.as_str();
+        if parsed.contains_key(name) {
+            return Err(source.error(
+                definition.name.span,
+                format!("definition \"{}\" defined twice", name),
+            ));
+        }
+        let ast = RegexAst::parse(&definition.pattern.text)
+            .map_err(|err| pattern_error(source, &definition.pattern, err))?;
+        parsed.insert(name, (*source, definition, ast));
     }
 
-    // replace all variables
-    // let old_difinations = res.clone();
-    let mut res: HashMap<String, String> = HashMap::new();
-    for (key, value) in &mut raw_definations {
-        res.insert(key.clone(), replace_regex_variables(value, &res)?.clone());
+    // 按依赖顺序替换变量
+    let mut res: HashMap<String, RegexDefinition> = HashMap::new();
+    for (_, definition) in definitions {
+        resolve_definition(&definition.name.text, &parsed, &mut res, &mut Vec::new())?;
     }
 
     Ok(res)
 }
 
+/// resolve_definition
+/// resolve the definitions name depends on, then name itself
+/// chain is the definitions being resolved, a reference back into it is a cycle
+/// a definition with params keeps its pattern unresolved,
+/// it is checked with every param bound to a single character
+fn resolve_definition<'a>(
+    name: &'a str,
+    parsed: &HashMap<&'a str, (Source, &'a SpecDefinition, RegexAst)>,
+    res: &mut HashMap<String, RegexDefinition>,
+    chain: &mut Vec<&'a str>,
+) -> Result<(), ParseError> {
+    if res.contains_key(name) {
+        return Ok(());
+    }
+
+    let (source, definition, ast) = &parsed[name];
+    let params: Vec<String> = definition
+        .params
+        .iter()
+        .map(|param| param.text.clone())
+        .collect();
+    chain.push(name);
+    for (reference, offset) in ast.references() {
+        // 参数不是definition
+        if params.iter().any(|param| param == reference) {
+            continue;
+        }
+        if let Some(index) = chain.iter().position(|each| *each == reference) {
+            let start = definition.pattern.span.start + offset;
+            return Err(source.error(
+                Span {
+                    start,
+                    end: start + reference.len() + 2,
+                },
+                format!(
+                    "definition cycle: {} -> {}",
+                    chain[index..].join(" -> "),
+                    reference
+                ),
+            ));
+        }
+        // 未定义的变量由resolve报错
+        if let Some((key, _)) = parsed.get_key_value(reference) {
+            resolve_definition(key, parsed, res, chain)?;
+        }
+    }
+    chain.pop();
+
+    let bound: HashMap<String, RegexAst> = params
+        .iter()
+        .map(|param| (param.clone(), RegexAst::Byte(b'a')))
+        .collect();
+    let value = ast
+        .clone()
+        .resolve_with(res, &bound)
+        .map_err(|err| pattern_error(source, &definition.pattern, err))?;
+    // 有参数的definition在使用时展开
+    let ast = if params.is_empty() {
+        value
+    } else {
+        ast.clone()
+    };
+    res.insert(name.to_string(), RegexDefinition { params, ast });
+
+    Ok(())
+}
+
 fn parse_rules(
-    rules: &str,
-    definitions: &HashMap<String, String>,
-) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    source: &Source,
+    rules: &[SpecRule],
+    definitions: &HashMap<String, RegexDefinition>,
+    conditions: &[Condition],
+    tokens: &[String],
+) -> Result<Vec<Rule>, ParseError> {
     let mut res = Vec::new();
-    for captures in Regex::new(r#"([\s\S]*?)->([\s\S]*?);;"#)
-        .unwrap()
-        .captures_iter(&rules)
-    {
-        let key = captures.get(1).unwrap().as_str().trim().to_string();
-        // 替换key中变量
-        let key = replace_regex_variables(&key, &definitions)?;
-        let value = captures.get(2).unwrap().as_str().trim().to_string();
-        res.push((key, value));
+    for rule in rules {
+        // <A,B>前缀
+        let mut rule_conditions = Vec::new();
+        for name in rule.conditions.iter() {
+            if name.text != "*" && !conditions.iter().any(|each| each.name == name.text) {
+                return Err(source.error(
+                    name.span,
+                    format!("start condition \"{}\" not declared", name.text),
+                ));
+            }
+            rule_conditions.push(name.text.clone());
+        }
+
+        // <<EOF>>规则没有正则
+        let eof = rule.pattern.text == EOF_PATTERN;
+        let regex = if eof {
+            RegexAst::Empty
+        } else {
+            // 解析key并替换其中变量
+            parse_pattern(source, &rule.pattern, definitions)?
+        };
+        let key = rule.pattern.text.clone();
+        let value = rule.action.text.clone();
+
+        // pattern => Kind
+        if rule.arrow.text == "=>" {
+            if !tokens.contains(&value) {
+                return Err(source.error(
+                    rule.action.span,
+                    format!("token \"{}\" not declared by %token", value),
+                ));
+            }
+
+            res.push(Rule {
+                pattern: key,
+                regex,
+                handler: String::new(),
+                conditions: rule_conditions,
+                eof,
+                token: Some(value),
+            });
+            continue;
+        }
+
+        // { ... } 块, ctx和text可以在块中使用
+        let handler = if value.starts_with('{') {
+            format!("|ctx, text| {}", value)
+        } else {
+            value
+        };
+
+        res.push(Rule {
+            pattern: key,
+            regex,
+            handler,
+            conditions: rule_conditions,
+            eof,
+            token: None,
+        });
     }
 
     Ok(res)
 }
 
-fn replace_regex_variables(
-    s: &str,
-    definitions: &HashMap<String, String>,
-) -> Result<String, Box<dyn Error>> {
-    let mut res = s.to_string();
-    let mut variables = Vec::new();
+/// parse_pattern
+/// parse a regex of the config file, `{name}` is replaced by the definition of name
+fn parse_pattern(
+    source: &Source,
+    pattern: &Item,
+    definitions: &HashMap<String, RegexDefinition>,
+) -> Result<RegexAst, ParseError> {
+    RegexAst::parse(&pattern.text)
+        .and_then(|ast| ast.resolve(definitions))
+        .map_err(|err| pattern_error(source, pattern, err))
+}
 
-    // find all variables
-    Regex::new(r"\{\w+\}")
-        .unwrap()
-        .captures_iter(&res)
-        .for_each(|captures| {
-            variables.push(captures[0].to_string());
-        });
+/// pattern_error
+/// the error of a regex, pointing at the bytes of the pattern it is about
+fn pattern_error(source: &Source, pattern: &Item, err: RegexError) -> ParseError {
+    let start = pattern.span.start + err.offset;
+    source.error(
+        Span {
+            start,
+            end: start + err.len.max(1),
+        },
+        err.message,
+    )
+}
 
-    // replace all variables
-    for variable in variables {
-        let variable_no_blanket = variable.replace("{", "").replace("}", "");
-        if let Some(val) = definitions.get(&variable_no_blanket) {
-            res = res.replace(&variable, format!("({})", val).as_str());
-        } else {
-            return Err(format!(
-                "parsing config error: variable \"{}\" not defined",
-                variable_no_blanket
-            )
-            .into());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build_dfa,
+        test_util::{self, parse_text},
+    };
+
+    /// spec
+    /// a .rlex text with definitions and one rule
+    fn spec(definitions: &str, pattern: &str) -> String {
+        format!(
+            "%{{\n%}}\n{}\n%%\n{} -> |_s| {{}} ;;\n%%\n",
+            definitions, pattern
+        )
+    }
+
+    fn error(definitions: &str, pattern: &str) -> ParseError {
+        *parse_text(&spec(definitions, pattern))
+            .err()
+            .unwrap()
+            .downcast::<ParseError>()
+            .unwrap()
+    }
+
+    /// matches
+    /// whether a rule of the config matches the whole input
+    fn matches(config: &Config, input: &str) -> bool {
+        test_util::matches(&build_dfa(config).unwrap().lookup_table, input.as_bytes())
+    }
+
+    #[test]
+    fn argument_count() {
+        let err = error("Pair(a, b) = {a}{b}", "x{Pair(y)}");
+        assert_eq!(
+            err.message,
+            "definition \"Pair\" takes 2 argument(s), 1 given"
+        );
+        assert_eq!((err.line, err.column), (5, 2));
+
+        let err = error("Pair(a, b) = {a}{b}", "{Pair}");
+        assert_eq!(
+            err.message,
+            "definition \"Pair\" takes 2 argument(s), 0 given"
+        );
+
+        let err = error("Digit = [0-9]", "{Digit(1)}");
+        assert_eq!(
+            err.message,
+            "definition \"Digit\" takes 0 argument(s), 1 given"
+        );
+    }
+
+    #[test]
+    fn nested_calls() {
+        let config = parse_text(&spec(
+            "Digit = [0-9]\nInner(p) = <{p}>\nOuter(q) = {Inner({q})}+",
+            "{Outer({Digit}|a)}",
+        ))
+        .unwrap();
+
+        assert!(matches(&config, "<1>"));
+        assert!(matches(&config, "<a><7><a>"));
+        assert!(!matches(&config, "<b>"));
+        assert!(!matches(&config, "<{Digit}>"));
+        assert!(!matches(&config, ""));
+    }
+
+    #[test]
+    fn params_in_classes() {
+        let config = parse_text(&spec("Not(c) = [^{c}\\n]", r#"\"{Not(\")}+\""#)).unwrap();
+        assert!(matches(&config, "\"ab é\""));
+        assert!(!matches(&config, "\"a\"b\""));
+
+        let config = parse_text(&spec("Either(a, b) = [{a}{b}]+", "{Either(x, [0-9])}")).unwrap();
+        assert!(matches(&config, "x0x9"));
+        assert!(!matches(&config, "xy"));
+    }
+
+    #[test]
+    fn cycles_through_params() {
+        let err = error("Wrap(x) = ({x})\nLoop = a{Wrap({Loop})}", "{Loop}");
+        assert_eq!(err.message, "definition cycle: Loop -> Loop");
+        assert_eq!((err.line, err.column), (4, 15));
+
+        let err = error("A(x) = {B({x})}\nB(y) = a|{A({y})}", "{A(z)}");
+        assert_eq!(err.message, "definition cycle: A -> B -> A");
+        assert_eq!((err.line, err.column), (4, 10));
+    }
+
+    #[test]
+    fn instantiation_errors_point_at_the_use() {
+        // 定义本身只用单个字符检查, 错误在展开时才出现
+        let err = error("Class(c) = [{c}x]", "ab{Class(yz)}");
+        assert_eq!(
+            err.message,
+            "in {Class(...)}: \"{c}\" in a character class must be a character or a character class"
+        );
+        assert_eq!((err.line, err.column), (5, 3));
+
+        let err = error(
+            "Inner(p) = [^{p}]\nOuter(q) = ({Inner({q})})*",
+            "{Outer(é)}",
+        );
+        assert_eq!(
+            err.message,
+            "in {Outer(...)}: in {Inner(...)}: non-ascii characters can't be used in [^...]"
+        );
+        assert_eq!((err.line, err.column), (6, 1));
+        assert_eq!(
+            err.to_string(),
+            "parsing config error: in {Outer(...)}: in {Inner(...)}: non-ascii characters can't be used in [^...]\n \
+--> test.rlex:6:1\n  |\n6 | {Outer(é)} -> |_s| {} ;;\n  | ^^^^^^^^^^"
+        );
+    }
+
+    /// write_files
+    /// write files into a directory of this test, return the path of the first one
+    fn write_files(test: &str, files: &[(&str, &str)]) -> String {
+        let dir = std::env::temp_dir().join(format!("rlex-{}-{}", test, std::process::id()));
+        for (name, text) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
         }
+        dir.join(files[0].0).to_string_lossy().into_owned()
+    }
+
+    /// summary
+    /// what a spec declares, to compare configs read from different formats
+    fn summary(config: &Config) -> Vec<String> {
+        let mut res = vec![format!(
+            "tokens {:?}, case-insensitive {}, backend {:?}",
+            config.tokens, config.options.case_insensitive, config.options.backend
+        )];
+        res.extend(
+            config
+                .conditions
+                .iter()
+                .map(|each| format!("condition {} {}", each.name, each.exclusive)),
+        );
+        res.extend(config.definitions.iter().map(|each| {
+            format!(
+                "definition {}({}) = {} {}",
+                each.name,
+                each.params.join(", "),
+                each.pattern,
+                each.included
+            )
+        }));
+        res.extend((0..config.rules.len()).map(|rule| {
+            let each = &config.rules[rule];
+            format!(
+                "rule {} {:?} {}",
+                config.describe_rule(rule),
+                each.token,
+                each.handler
+            )
+        }));
+        res
+    }
+
+    #[test]
+    fn includes() {
+        let main = write_files(
+            "includes",
+            &[
+                (
+                    "main.rlex",
+                    "%{\n%}\n    %include \"lib/common.rlex\"\n    %include \"lib/chars.rlex\"\n    %token Identifier\n%%\n    {Ident} => Identifier ;;\n%%\n",
+                ),
+                (
+                    "lib/common.rlex",
+                    "%include \"chars.rlex\"\nIdent = {Letter}({Letter}|{Digit})*\n%%\n    \" \" -> |_s| {} ;;\n",
+                ),
+                ("lib/chars.rlex", "// 两次include只读一次\nLetter = [a-z]\nDigit = [0-9]\n"),
+            ],
+        );
+        let config = parse_config(&main).unwrap();
+
+        // 被include的文件在前, 路径相对于include它的文件
+        let definitions: Vec<_> = config
+            .definitions
+            .iter()
+            .map(|each| (each.name.as_str(), each.included))
+            .collect();
+        assert_eq!(
+            definitions,
+            vec![("Letter", true), ("Digit", true), ("Ident", true)]
+        );
+        let rules: Vec<_> = (0..config.rules.len())
+            .map(|rule| config.describe_rule(rule))
+            .collect();
+        assert_eq!(rules, vec!["\" \"", "{Ident}"]);
+        assert_eq!(config.tokens, vec!["Identifier"]);
+        assert!(matches(&config, " "));
+        assert!(matches(&config, "a1"));
+        assert!(!matches(&config, "1a"));
+    }
+
+    #[test]
+    fn include_errors() {
+        let main = write_files(
+            "include_cycles",
+            &[
+                ("main.rlex", "%{\n%}\n    %include \"a.rlex\"\n%%\n%%\n"),
+                ("a.rlex", "%include \"b.rlex\"\n"),
+                ("b.rlex", "A = a\n  %include \"a.rlex\"\n"),
+            ],
+        );
+        let err = *parse_config(&main)
+            .err()
+            .unwrap()
+            .downcast::<ParseError>()
+            .unwrap();
+        let dir = Path::new(&main).parent().unwrap();
+        let (a, b) = (dir.join("a.rlex"), dir.join("b.rlex"));
+        assert_eq!(
+            err.message,
+            format!(
+                "include cycle: {} -> {} -> {}",
+                a.display(),
+                b.display(),
+                a.display()
+            )
+        );
+        assert_eq!(err.path, b.display().to_string());
+        assert_eq!((err.line, err.column), (2, 12));
+
+        let main = write_files(
+            "include_missing",
+            &[("main.rlex", "%{\n%}\n    %include \"none.rlex\"\n%%\n%%\n")],
+        );
+        let err = *parse_config(&main)
+            .err()
+            .unwrap()
+            .downcast::<ParseError>()
+            .unwrap();
+        assert!(err.message.starts_with(&format!(
+            "can't read \"{}\": ",
+            Path::new(&main)
+                .parent()
+                .unwrap()
+                .join("none.rlex")
+                .display()
+        )));
+        assert_eq!((err.line, err.column), (3, 14));
+    }
+
+    #[test]
+    fn toml_and_json_documents() {
+        let rlex = "%{\n    use std::fmt;\n%}\n    %x COMMENT\n    %token Str, Int\n    %option case-insensitive\n    %option backend=match\n    Quoted(q) = {q}[^{q}]*{q}\n    Digit = [0-9]\n%%\n    {Quoted(\\\")} => Str ;;\n    {Digit}+ => Int ;;\n    \"/*\" -> |ctx, _s| { ctx.begin(COMMENT); } ;;\n    <COMMENT>\"*/\" -> |ctx, _s| { ctx.begin(INITIAL); } ;;\n%%\n    pub count: usize,\n";
+        let toml = r#"declarations = "    use std::fmt;"
+tokens = ["Str", "Int"]
+variables = "    pub count: usize,"
+
+[options]
+case-insensitive = true
+backend = "match"
+
+[[conditions]]
+name = "COMMENT"
+exclusive = true
+
+[[definitions]]
+name = "Quoted"
+params = ["q"]
+pattern = '{q}[^{q}]*{q}'
+
+[[definitions]]
+name = "Digit"
+pattern = '[0-9]'
+
+[[rules]]
+pattern = '{Quoted(\")}'
+token = "Str"
+
+[[rules]]
+pattern = '{Digit}+'
+token = "Int"
+
+[[rules]]
+pattern = '"/*"'
+action = "|ctx, _s| { ctx.begin(COMMENT); }"
+
+[[rules]]
+conditions = ["COMMENT"]
+pattern = '"*/"'
+action = "|ctx, _s| { ctx.begin(INITIAL); }"
+"#;
+        let expected = summary(&parse_text(rlex).unwrap());
+
+        let toml_path = write_files("documents", &[("lexer.toml", toml)]);
+        let config = parse_config(&toml_path).unwrap();
+        assert_eq!(summary(&config), expected);
+
+        // json和toml读出同样的文档
+        let document = SpecDocument::from_toml(&toml_path, toml).unwrap();
+        let json = serde_json::to_string(&document).unwrap();
+        let json_path = write_files("documents", &[("lexer.json", &json)]);
+        assert_eq!(summary(&parse_config(&json_path).unwrap()), expected);
+
+        // 写成.rlex再读回来
+        assert_eq!(
+            summary(&parse_text(&document.to_rlex().unwrap()).unwrap()),
+            expected
+        );
+        assert_eq!(
+            SpecDocument::from_json(&json_path, &json).unwrap(),
+            document
+        );
+    }
+
+    #[test]
+    fn rules_with_action_and_token() {
+        let json = r#"{"rules": [{"pattern": "a", "token": "A"}, {"pattern": "b", "action": "|_s| {}", "token": "A"}], "tokens": ["A"]}"#;
+        let document = SpecDocument::from_json("lexer.json", json).unwrap();
+
+        assert_eq!(
+            document.to_rlex().err().unwrap().to_string(),
+            "parsing config error: rules[1]: a rule needs exactly one of \"action\" and \"token\""
+        );
+        assert_eq!(
+            document.to_config("lexer.json").err().unwrap().to_string(),
+            "parsing config error: a rule needs exactly one of \"action\" and \"token\"\n --> lexer.json: rules[1]:1:1\n  |\n1 | b \n  | ^"
+        );
+    }
+
+    #[test]
+    fn document_error_positions() {
+        let err = SpecDocument::from_toml("lexer.toml", "tokens = [\"Str\"]\nrules = 1\n")
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            err.starts_with(
+                "parsing config error: lexer.toml: TOML parse error at line 2, column 9"
+            ),
+            "{}",
+            err
+        );
+
+        let err = SpecDocument::from_json("lexer.json", "{\n  \"tokens\": [\"Str\",]\n}")
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            err,
+            "parsing config error: lexer.json: trailing comma at line 2 column 20"
+        );
+
+        // 每个条目写成.rlex的一行, 错误指向其中
+        let json = r#"{"rules": [{"pattern": "a", "token": "A"}, {"pattern": "{M}", "action": "|_s| {}"}], "tokens": ["A"]}"#;
+        let err = SpecDocument::from_json("lexer.json", json)
+            .unwrap()
+            .to_config("lexer.json")
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            err,
+            "parsing config error: variable \"M\" not defined\n --> lexer.json: rules[1]:1:1\n  |\n1 | {M} -> |_s| {}\n  | ^^^"
+        );
     }
-    Ok(res)
 }
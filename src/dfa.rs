@@ -15,6 +15,12 @@ pub struct StateVertex {
 
 pub struct DfaVertexRef(Rc<RefCell<StateVertex>>);
 
+impl Default for DfaVertexRef {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DfaVertexRef {
     pub fn new() -> Self {
         DfaVertexRef(Rc::new(RefCell::new(StateVertex {
@@ -92,11 +98,22 @@ impl LookupState {
 #[derive(Serialize, Deserialize)]
 pub struct LookupTable {
     pub states: Vec<LookupState>,
+    /// start state of each start condition
+    pub starts: Vec<usize>,
+}
+
+impl Default for LookupTable {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LookupTable {
     pub fn new() -> Self {
-        LookupTable { states: Vec::new() }
+        LookupTable {
+            states: Vec::new(),
+            starts: Vec::new(),
+        }
     }
 }
 
@@ -110,15 +127,27 @@ impl Dfa {
     /// build dfa from nfa
     /// return start vertex of dfa
     pub fn build(nfa: &Nfa) -> Dfa {
-        // 以nfa的开始节点的epsilon-closure为开始节点
-        let start = DfaVertexRef::new();
-
-        nfa.start
-            .epsilon_closure(&mut start.borrow_mut().epsilon_closure);
+        Self::build_with_conditions(std::slice::from_ref(nfa))
+    }
 
-        // 递归扩展
+    /// build_with_conditions
+    /// build one dfa from the nfa of each start condition
+    /// lookup_table.starts[i] is the start state of nfas[i]
+    pub fn build_with_conditions(nfas: &[Nfa]) -> Dfa {
         let mut visited = Vec::new();
-        Self::tarverse_vertex(DfaVertexRef::clone(&start), &mut visited);
+        let mut starts = Vec::new();
+
+        for nfa in nfas {
+            // 以nfa的开始节点的epsilon-closure为开始节点
+            let start = DfaVertexRef::new();
+
+            nfa.start
+                .epsilon_closure(&mut start.borrow_mut().epsilon_closure);
+
+            // 递归扩展
+            Self::tarverse_vertex(DfaVertexRef::clone(&start), &mut visited);
+            starts.push(start);
+        }
 
         // 标记每个节点的handler
         visited.iter().for_each(|each| {
@@ -137,7 +166,7 @@ impl Dfa {
 
         let mut lookup_table = LookupTable::new();
         // 生成lookup table
-        visited.iter().enumerate().for_each(|(_, each)| {
+        visited.iter().for_each(|each| {
             let mut curr_state = LookupState::new();
 
             // 指定handler
@@ -158,6 +187,12 @@ impl Dfa {
             lookup_table.states.push(curr_state);
         });
 
+        // 记录每个start condition的开始状态
+        lookup_table.starts = starts
+            .iter()
+            .map(|start| visited.iter().position(|each| each == start).unwrap())
+            .collect();
+
         Dfa {
             vertexs: visited,
             lookup_table,
@@ -0,0 +1,1083 @@
+use crate::{
+    spec_document::{ConditionSpec, DefinitionSpec, OptionValue, RuleSpec, SpecDocument},
+    RegexAst,
+};
+use regex::Regex;
+
+/// FlexImport
+/// a flex spec converted to a SpecDocument, with the constructs that were not converted
+pub struct FlexImport {
+    pub document: SpecDocument,
+    pub notes: Vec<FlexNote>,
+}
+
+/// FlexNote
+/// an unsupported construct at a line of the .l file, and what was done with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlexNote {
+    pub line: usize,
+    pub message: String,
+}
+
+/// options that only matter to the generated C scanner
+const C_OPTIONS: [&str; 10] = [
+    "yywrap",
+    "noyywrap",
+    "nounput",
+    "noinput",
+    "8bit",
+    "batch",
+    "never-interactive",
+    "interactive",
+    "warn",
+    "nowarn",
+];
+
+/// import_flex
+/// convert a flex .l file: definitions, start conditions, `%option`s and rules
+/// `return NAME;` actions become token rules, `BEGIN(NAME);` actions begin the condition,
+/// other C actions are kept as comments in empty handlers
+/// C code blocks are dropped, every construct not converted as written is listed in notes
+pub fn import_flex(text: &str) -> FlexImport {
+    let mut importer = Importer {
+        text,
+        pos: 0,
+        document: SpecDocument::default(),
+        notes: Vec::new(),
+    };
+    importer.definitions();
+    importer.rules();
+
+    // 第二个%%之后是C代码
+    if !text[importer.pos..].trim().is_empty() {
+        importer.note(
+            importer.pos,
+            "the user code section is C code, it is dropped",
+        );
+    }
+
+    FlexImport {
+        document: importer.document,
+        notes: importer.notes,
+    }
+}
+
+/// Importer
+/// pos is the start of the next line of text
+struct Importer<'a> {
+    text: &'a str,
+    pos: usize,
+    document: SpecDocument,
+    notes: Vec<FlexNote>,
+}
+
+impl<'a> Importer<'a> {
+    fn note(&mut self, offset: usize, message: impl Into<String>) {
+        self.notes.push(FlexNote {
+            line: self.text[..offset].matches('\n').count() + 1,
+            message: message.into(),
+        });
+    }
+
+    /// the next line without its newline, and its offset
+    fn next_line(&mut self) -> Option<(usize, &'a str)> {
+        if self.pos >= self.text.len() {
+            return None;
+        }
+
+        let start = self.pos;
+        let end = self.text[start..]
+            .find('\n')
+            .map_or(self.text.len(), |index| start + index);
+        self.pos = (end + 1).min(self.text.len());
+        Some((start, self.text[start..end].trim_end_matches('\r')))
+    }
+
+    /// skip lines until one starting with end, after trimming
+    fn skip_until(&mut self, end: &str) {
+        while let Some((_, line)) = self.next_line() {
+            if line.trim_start().starts_with(end) {
+                break;
+            }
+        }
+    }
+
+    /// skip a `/* ... */` comment starting at offset
+    fn skip_comment(&mut self, offset: usize) {
+        self.pos = match self.text[offset..].find("*/") {
+            Some(index) => offset + index + 2,
+            None => self.text.len(),
+        };
+        // 跳过注释所在行的剩余部分
+        self.next_line();
+    }
+
+    /// definitions section, up to the first `%%`
+    fn definitions(&mut self) {
+        while let Some((offset, line)) = self.next_line() {
+            let trimmed = line.trim();
+            if trimmed == "%%" {
+                return;
+            }
+            if trimmed.is_empty() {
+                continue;
+            }
+            if trimmed.starts_with("/*") {
+                self.skip_comment(offset + (line.len() - line.trim_start().len()));
+                continue;
+            }
+            if line.starts_with("%{") {
+                self.note(offset, "C code in %{ %} is not supported, it is dropped");
+                self.skip_until("%}");
+                continue;
+            }
+            if line.starts_with("%top") {
+                self.note(offset, "%top is not supported, it is dropped");
+                self.skip_until("}");
+                continue;
+            }
+            if line.starts_with(char::is_whitespace) {
+                self.note(offset, "indented C code is not supported, it is dropped");
+                continue;
+            }
+            if line.starts_with('%') {
+                self.directive(offset, line);
+                continue;
+            }
+            self.definition(offset, line);
+        }
+
+        self.note(
+            self.text.len(),
+            "no %% before the rules, the spec has no rules",
+        );
+    }
+
+    /// `%s`, `%x` and `%option` lines
+    fn directive(&mut self, offset: usize, line: &str) {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        match name {
+            "%s" | "%start" | "%x" | "%exclusive" => {
+                let exclusive = name == "%x" || name == "%exclusive";
+                for word in words {
+                    if !self
+                        .document
+                        .conditions
+                        .iter()
+                        .any(|each| each.name == word)
+                    {
+                        self.document.conditions.push(ConditionSpec {
+                            name: word.to_string(),
+                            exclusive,
+                        });
+                    }
+                }
+            }
+            "%option" => words.for_each(|word| self.option(offset, word)),
+            // yytext的类型, 与rust无关
+            "%pointer" | "%array" => {}
+            _ => self.note(
+                offset,
+                format!("directive {} is not supported, it is ignored", name),
+            ),
+        }
+    }
+
+    fn option(&mut self, offset: usize, option: &str) {
+        let (key, value) = match option.split_once('=') {
+            Some((key, value)) => (key, Some(value.trim_matches('"'))),
+            None => (option, None),
+        };
+
+        let (key, value) = match (key, value) {
+            ("caseless" | "case-insensitive", None) => {
+                ("case-insensitive", OptionValue::Flag(true))
+            }
+            ("case-sensitive", None) => ("case-insensitive", OptionValue::Flag(false)),
+            ("yylineno", None) => ("lines", OptionValue::Flag(true)),
+            // 默认规则输出未匹配的输入, nodefault时报错
+            ("default", None) => ("error", OptionValue::Value("print".to_string())),
+            ("nodefault", None) => ("error", OptionValue::Value("fail".to_string())),
+            (key, None) if C_OPTIONS.contains(&key) => return,
+            _ => {
+                return self.note(
+                    offset,
+                    format!("%option {} is not supported, it is ignored", option),
+                )
+            }
+        };
+        self.document.options.insert(key.to_string(), value);
+    }
+
+    /// `name pattern`, `-` in names becomes `_`
+    fn definition(&mut self, offset: usize, line: &str) {
+        let (name, pattern) = match line.split_once(char::is_whitespace) {
+            Some((name, pattern)) => (name, pattern.trim()),
+            None => (line, ""),
+        };
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid || pattern.is_empty() {
+            return self.note(
+                offset,
+                format!("\"{}\" is not a definition, it is dropped", line),
+            );
+        }
+
+        if let Some(pattern) = self.pattern(offset, pattern) {
+            self.document.definitions.push(DefinitionSpec {
+                name: name.replace('-', "_"),
+                params: Vec::new(),
+                pattern,
+            });
+        }
+    }
+
+    /// a flex pattern in the rlex dialect, None if it can't be converted
+    fn pattern(&mut self, offset: usize, pattern: &str) -> Option<String> {
+        let mut translator = Translator {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            notes: Vec::new(),
+        };
+        let res = translator.seq(false).and_then(|res| {
+            RegexAst::parse(&res)
+                .map(|_| res)
+                .map_err(|err| err.message)
+        });
+        for message in translator.notes {
+            self.note(offset, format!("in `{}`: {}", pattern, message));
+        }
+
+        match res {
+            Ok(res) => Some(res),
+            Err(err) => {
+                self.note(offset, format!("`{}` is dropped: {}", pattern, err));
+                None
+            }
+        }
+    }
+
+    /// rules section, up to the second `%%`
+    /// `<A>{ ... }` scopes add A to the rules inside,
+    /// rules with the action `|` take the action of the next rule
+    fn rules(&mut self) {
+        let mut scopes: Vec<Vec<String>> = Vec::new();
+        let mut pending: Vec<RuleSpec> = Vec::new();
+
+        while let Some((offset, line)) = self.next_line() {
+            let trimmed = line.trim();
+            if trimmed == "%%" {
+                break;
+            }
+            if trimmed.is_empty() {
+                continue;
+            }
+            if trimmed == "}" && !scopes.is_empty() {
+                scopes.pop();
+                continue;
+            }
+            if trimmed.starts_with("/*") {
+                self.skip_comment(offset + (line.len() - line.trim_start().len()));
+                continue;
+            }
+            if line.starts_with("%{") {
+                self.note(offset, "C code in %{ %} is not supported, it is dropped");
+                self.skip_until("%}");
+                continue;
+            }
+            if line.starts_with(char::is_whitespace) {
+                self.note(offset, "indented C code is not supported, it is dropped");
+                continue;
+            }
+
+            // <A,B>前缀
+            let mut conditions: Vec<String> = scopes.concat();
+            let mut rest = line;
+            if rest.starts_with('<') && !rest.starts_with("<<EOF>>") {
+                let close = match rest.find('>') {
+                    Some(index) => index,
+                    None => {
+                        self.note(offset, "unclosed \"<\", the rule is dropped");
+                        continue;
+                    }
+                };
+                conditions.extend(
+                    rest[1..close]
+                        .split(',')
+                        .map(|each| each.trim().to_string()),
+                );
+                rest = &rest[close + 1..];
+            }
+            if rest.trim() == "{" {
+                scopes.push(conditions[scopes.concat().len()..].to_vec());
+                continue;
+            }
+
+            let pattern_len = if rest.starts_with("<<EOF>>") {
+                "<<EOF>>".len()
+            } else {
+                pattern_end(rest)
+            };
+            let pattern = &rest[..pattern_len];
+            let action_start = offset + (line.len() - rest.len()) + pattern_len;
+            let action = self.action_code(action_start);
+
+            let pattern = if pattern == "<<EOF>>" {
+                Some(pattern.to_string())
+            } else {
+                self.pattern(offset, pattern)
+            };
+            if let Some(pattern) = pattern {
+                pending.push(RuleSpec {
+                    conditions,
+                    pattern,
+                    action: None,
+                    token: None,
+                });
+            }
+
+            // |: 与下一条规则相同的action
+            if action == "|" {
+                continue;
+            }
+            let (action, token) = self.action(offset, &action);
+            for mut rule in pending.drain(..) {
+                rule.action = action.clone();
+                rule.token = token.clone();
+                self.document.rules.push(rule);
+            }
+        }
+
+        if !pending.is_empty() {
+            self.note(
+                self.pos,
+                "the last rule has the action \"|\", it is dropped",
+            );
+        }
+    }
+
+    /// the C action starting at start, a braced block may span several lines
+    fn action_code(&mut self, start: usize) -> String {
+        let start = start
+            + (self.text[start..].len() - self.text[start..].trim_start_matches([' ', '\t']).len());
+        if self.text[start..].starts_with('{') {
+            if let Some(end) = c_block_end(self.text, start) {
+                // 跳过}所在行的剩余部分
+                self.pos = end;
+                self.next_line();
+                return self.text[start..end].to_string();
+            }
+        }
+        if self.text[start..].starts_with("%{") {
+            let end = self.text[start..]
+                .find("%}")
+                .map_or(self.text.len(), |index| start + index);
+            self.pos = end;
+            self.next_line();
+            return format!("{{{}}}", &self.text[start + 2..end]);
+        }
+
+        // 到行尾为止, pos已经在下一行
+        let end = self.text[start..]
+            .find('\n')
+            .map_or(self.text.len(), |index| start + index);
+        self.text[start..end].trim().to_string()
+    }
+
+    /// (action, token) of a rule
+    /// comments are ignored when the action is recognized
+    fn action(&mut self, offset: usize, code: &str) -> (Option<String>, Option<String>) {
+        let strip_braces = |code: &str| {
+            let code = code.trim();
+            match code
+                .strip_prefix('{')
+                .and_then(|code| code.strip_suffix('}'))
+            {
+                Some(code) => code.trim().to_string(),
+                None => code.to_string(),
+            }
+        };
+        let (stripped, body) = (strip_braces(&strip_c_comments(code)), strip_braces(code));
+        let stripped = stripped.as_str();
+
+        let token = Regex::new(r"^return\s*\(?\s*([A-Za-z_]\w*)\s*\)?\s*;$").unwrap();
+        let begin = Regex::new(r"^BEGIN\s*\(?\s*([A-Za-z_]\w*)\s*\)?\s*;$").unwrap();
+        if stripped.is_empty() || stripped == ";" {
+            return (Some("|_s| {}".to_string()), None);
+        }
+        if let Some(captures) = token.captures(stripped) {
+            let name = captures[1].to_string();
+            if !self.document.tokens.contains(&name) {
+                self.document.tokens.push(name.clone());
+            }
+            return (None, Some(name));
+        }
+        if let Some(captures) = begin.captures(stripped) {
+            return (
+                Some(format!("|ctx, _s| {{ ctx.begin({}); }}", &captures[1])),
+                None,
+            );
+        }
+
+        // 其他C代码作为注释保留
+        self.note(offset, "the C action is kept as a comment");
+        let mut handler = "|_s| {\n".to_string();
+        for line in body.lines() {
+            handler.push_str(&format!("    // {}\n", line.trim()));
+        }
+        handler.push('}');
+        (Some(handler), None)
+    }
+}
+
+/// pattern_end
+/// a flex pattern ends at whitespace outside quotes and classes
+fn pattern_end(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut pos = 0;
+    let mut in_string = false;
+    let mut in_class = false;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'\\' => pos += 1,
+            b'"' if !in_class => in_string = !in_string,
+            b'[' if in_class && bytes.get(pos + 1) == Some(&b':') => {
+                pos = text[pos..]
+                    .find(":]")
+                    .map_or(bytes.len(), |index| pos + index + 1);
+            }
+            b']' if in_class => in_class = false,
+            b'[' if !in_string => {
+                in_class = true;
+                // []...] 和 [^]...] 中第一个]是字符
+                if bytes.get(pos + 1) == Some(&b'^') {
+                    pos += 1;
+                }
+                if bytes.get(pos + 1) == Some(&b']') {
+                    pos += 1;
+                }
+            }
+            b' ' | b'\t' if !in_string && !in_class => return pos,
+            _ => {}
+        }
+        pos += 1;
+    }
+
+    bytes.len()
+}
+
+/// c_block_end
+/// the offset after the `}` closing the `{` at start, C strings and comments are skipped
+fn c_block_end(text: &str, start: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut pos = start;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(pos + 1);
+                }
+            }
+            quote @ (b'"' | b'\'') => {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos] != quote {
+                    if bytes[pos] == b'\\' {
+                        pos += 1;
+                    }
+                    pos += 1;
+                }
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'*') => {
+                pos = text[pos + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |index| pos + 2 + index + 1);
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'/') => {
+                pos = text[pos..]
+                    .find('\n')
+                    .map_or(bytes.len(), |index| pos + index);
+            }
+            _ => {}
+        }
+        pos += 1;
+    }
+
+    None
+}
+
+/// strip_c_comments
+/// code with each C comment replaced by a space, strings are kept as they are
+fn strip_c_comments(code: &str) -> String {
+    let bytes = code.as_bytes();
+    let mut res = String::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let start = pos;
+        match bytes[pos] {
+            quote @ (b'"' | b'\'') => {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos] != quote {
+                    if bytes[pos] == b'\\' {
+                        pos += 1;
+                    }
+                    pos += 1;
+                }
+                pos = (pos + 1).min(bytes.len());
+                res.push_str(&code[start..pos]);
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'*') => {
+                pos = code[pos + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |index| pos + 2 + index + 2);
+                res.push(' ');
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'/') => {
+                pos = code[pos..]
+                    .find('\n')
+                    .map_or(bytes.len(), |index| pos + index);
+                res.push(' ');
+            }
+            _ => {
+                pos += code[pos..].chars().next().map_or(1, char::len_utf8);
+                res.push_str(&code[start..pos]);
+            }
+        }
+    }
+
+    res
+}
+
+/// Translator
+/// rewrites a flex pattern in the rlex dialect, pos is the next char
+/// notes are the constructs dropped from the pattern
+struct Translator {
+    chars: Vec<char>,
+    pos: usize,
+    notes: Vec<String>,
+}
+
+impl Translator {
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).copied();
+        self.pos += 1;
+        c
+    }
+
+    /// a sequence of alternatives, up to `)` if nested
+    fn seq(&mut self, nested: bool) -> Result<String, String> {
+        let mut res = String::new();
+        // 最后一个原子在res中的开始位置, 重复作用于它
+        let mut last: Option<usize> = None;
+
+        while let Some(&c) = self.chars.get(self.pos) {
+            let start = res.len();
+            match c {
+                ')' if nested => break,
+                ')' => return Err("unmatched \")\"".to_string()),
+                '|' => {
+                    self.pos += 1;
+                    res.push('|');
+                    last = None;
+                    continue;
+                }
+                '(' if self.chars.get(self.pos + 1) == Some(&'?') => {
+                    return Err("(?...) groups are not supported".to_string())
+                }
+                '(' => {
+                    self.pos += 1;
+                    let inner = self.seq(true)?;
+                    if self.next() != Some(')') {
+                        return Err("unclosed \"(\"".to_string());
+                    }
+                    res.push_str(&format!("({})", inner));
+                }
+                '*' | '+' | '?' => {
+                    self.pos += 1;
+                    if last.is_none() {
+                        return Err(format!("\"{}\" has nothing to repeat", c));
+                    }
+                    res.push(c);
+                    continue;
+                }
+                '{' => {
+                    self.pos += 1;
+                    let content: String = self.chars[self.pos..]
+                        .iter()
+                        .take_while(|c| **c != '}')
+                        .collect();
+                    self.pos += content.chars().count() + 1;
+
+                    // {n,m}重复前一个原子
+                    if !content.is_empty()
+                        && content.chars().all(|c| c.is_ascii_digit() || c == ',')
+                    {
+                        let from = last.ok_or("repetition has nothing to repeat")?;
+                        let atom = res.split_off(from);
+                        res.push_str(&repeat(&atom, &content)?);
+                        continue;
+                    }
+                    let is_name = content
+                        .starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                        && content
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+                    if !is_name {
+                        return Err(format!("\"{{{}}}\" is not supported", content));
+                    }
+                    res.push_str(&format!("{{{}}}", content.replace('-', "_")));
+                }
+                '[' => {
+                    self.pos += 1;
+                    let class = self.class()?;
+                    res.push_str(&class);
+                }
+                '"' => {
+                    self.pos += 1;
+                    res.push('"');
+                    loop {
+                        match self.next() {
+                            Some('"') => break,
+                            Some('\\') => res.push_str(&self.escape()?),
+                            // 字符串中的符号不需要转义
+                            Some(c) if c.is_ascii_punctuation() || c == ' ' => res.push(c),
+                            Some(c) => res.push_str(&lit(c)),
+                            None => return Err("unclosed \"\\\"\"".to_string()),
+                        }
+                    }
+                    res.push('"');
+                }
+                '.' => {
+                    self.pos += 1;
+                    res.push_str("[^\\n]");
+                }
+                '^' if self.pos == 0 => {
+                    self.pos += 1;
+                    self.notes.push(
+                        "the `^` anchor is not supported, the rule also matches inside a line"
+                            .to_string(),
+                    );
+                    continue;
+                }
+                '$' if !nested && self.pos + 1 == self.chars.len() => {
+                    self.pos += 1;
+                    self.notes.push(
+                        "the `$` anchor is not supported, the rule also matches before other characters"
+                            .to_string(),
+                    );
+                    continue;
+                }
+                '/' if !nested => {
+                    let context: String = self.chars[self.pos..].iter().collect();
+                    self.notes.push(format!(
+                        "trailing context `{}` is not supported, it is dropped",
+                        context
+                    ));
+                    self.pos = self.chars.len();
+                    break;
+                }
+                '\\' => {
+                    self.pos += 1;
+                    let escape = self.escape()?;
+                    res.push_str(&escape);
+                }
+                c => {
+                    self.pos += 1;
+                    res.push_str(&lit(c));
+                }
+            }
+            last = Some(start);
+        }
+
+        Ok(res)
+    }
+
+    /// a class after `[`, `[:alpha:]` expressions are expanded
+    fn class(&mut self) -> Result<String, String> {
+        let mut res = "[".to_string();
+        if self.chars.get(self.pos) == Some(&'^') {
+            self.pos += 1;
+            res.push('^');
+        }
+
+        let mut first = true;
+        loop {
+            let c = self.next().ok_or("unclosed \"[\"")?;
+            match c {
+                ']' if !first => break,
+                '[' if self.chars.get(self.pos) == Some(&':') => {
+                    let name: String = self.chars[self.pos + 1..]
+                        .iter()
+                        .take_while(|c| **c != ':')
+                        .collect();
+                    self.pos += name.chars().count() + 3;
+                    res.push_str(&class_expression(&name)?);
+                }
+                '\\' => res.push_str(&self.escape()?),
+                // 开头和结尾的-是字符
+                '-' if first || self.chars.get(self.pos) == Some(&']') => res.push_str("\\-"),
+                '-' => res.push('-'),
+                ' ' => res.push(' '),
+                c => res.push_str(&lit(c)),
+            }
+            first = false;
+        }
+
+        res.push(']');
+        Ok(res)
+    }
+
+    /// the character after `\`
+    fn escape(&mut self) -> Result<String, String> {
+        let c = self.next().ok_or("\"\\\" at the end of the pattern")?;
+        let digits = |translator: &mut Self, radix: u32, max: usize| {
+            let text: String = translator.chars[translator.pos..]
+                .iter()
+                .take(max)
+                .take_while(|c| c.is_digit(radix))
+                .collect();
+            translator.pos += text.len();
+            text
+        };
+
+        Ok(match c {
+            'n' => "\\n".to_string(),
+            't' => "\\t".to_string(),
+            'r' => "\\r".to_string(),
+            'f' => "\\f".to_string(),
+            'v' => "\\v".to_string(),
+            'a' => "\\x07".to_string(),
+            'b' => "\\x08".to_string(),
+            '0'..='7' => {
+                self.pos -= 1;
+                let octal = digits(self, 8, 3);
+                let byte = u8::from_str_radix(&octal, 8)
+                    .map_err(|_| format!("invalid escape \\{}", octal))?;
+                byte_lit(byte)
+            }
+            'x' => {
+                let hex = digits(self, 16, 2);
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) => byte_lit(byte),
+                    Err(_) => lit('x'),
+                }
+            }
+            c => lit(c),
+        })
+    }
+}
+
+/// repeat
+/// atom{n}, atom{n,} or atom{n,m}
+fn repeat(atom: &str, range: &str) -> Result<String, String> {
+    let parse = |text: &str| {
+        text.parse::<usize>()
+            .map_err(|_| format!("invalid repetition {{{}}}", range))
+    };
+    let (min, max) = match range.split_once(',') {
+        None => (parse(range)?, Some(parse(range)?)),
+        Some((min, "")) => (parse(min)?, None),
+        Some((min, max)) => (parse(min)?, Some(parse(max)?)),
+    };
+
+    let mut res = atom.repeat(min);
+    match max {
+        Some(max) if max < min => return Err(format!("invalid repetition {{{}}}", range)),
+        // x{n,m} 即 n个x之后 m-n个x?
+        Some(max) => (min..max).for_each(|_| res.push_str(&format!("{}?", atom))),
+        None => res.push_str(&format!("{}*", atom)),
+    }
+    if res.is_empty() {
+        res.push_str("()");
+    }
+
+    Ok(res)
+}
+
+/// class_expression
+/// `[:name:]` as the inside of a class
+fn class_expression(name: &str) -> Result<String, String> {
+    let range = |from: char, to: char| format!("{}-{}", lit(from), lit(to));
+    Ok(match name {
+        "alpha" => "a-zA-Z".to_string(),
+        "digit" => "0-9".to_string(),
+        "alnum" => "a-zA-Z0-9".to_string(),
+        "upper" => "A-Z".to_string(),
+        "lower" => "a-z".to_string(),
+        "xdigit" => "0-9a-fA-F".to_string(),
+        "space" => " \\t\\n\\r\\f\\v".to_string(),
+        "blank" => " \\t".to_string(),
+        "cntrl" => "\\x00-\\x1F\\x7F".to_string(),
+        "print" => range(' ', '~'),
+        "graph" => range('!', '~'),
+        "punct" => [('!', '/'), (':', '@'), ('[', '`'), ('{', '~')]
+            .iter()
+            .map(|(from, to)| range(*from, *to))
+            .collect(),
+        _ => return Err(format!("[:{}:] is not supported", name)),
+    })
+}
+
+/// lit
+/// a character matching itself in the rlex dialect
+fn lit(c: char) -> String {
+    match c {
+        c if c.is_alphanumeric() || c == '_' || !c.is_ascii() => c.to_string(),
+        '\n' => "\\n".to_string(),
+        '\t' => "\\t".to_string(),
+        '\r' => "\\r".to_string(),
+        '\0' => "\\0".to_string(),
+        '\x0C' => "\\f".to_string(),
+        '\x0B' => "\\v".to_string(),
+        c if c.is_ascii_punctuation() || c == ' ' => format!("\\{}", c),
+        c => format!("\\x{:02X}", c as u32),
+    }
+}
+
+/// byte_lit
+/// a byte matching itself, bytes after 0x7F are not characters
+fn byte_lit(byte: u8) -> String {
+    if byte.is_ascii() {
+        lit(byte as char)
+    } else {
+        format!("\\x{:02X}", byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// rules
+    /// the conditions, pattern, action and token of each rule
+    fn rules(import: &FlexImport) -> Vec<(String, String, Option<&str>, Option<&str>)> {
+        import
+            .document
+            .rules
+            .iter()
+            .map(|rule| {
+                (
+                    rule.conditions.join(","),
+                    rule.pattern.clone(),
+                    rule.action.as_deref(),
+                    rule.token.as_deref(),
+                )
+            })
+            .collect()
+    }
+
+    fn patterns(import: &FlexImport) -> Vec<&str> {
+        import
+            .document
+            .rules
+            .iter()
+            .map(|rule| rule.pattern.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn counted_repeats() {
+        let import = import_flex(
+            "ALPHA [a-z]\n%%\n{ALPHA}{2,3} return ID;\nx{3}|y{1,} return XY;\n(ab){0,2}c return C;\n",
+        );
+        assert_eq!(
+            patterns(&import),
+            vec!["{ALPHA}{ALPHA}{ALPHA}?", "xxx|yy*", "(ab)?(ab)?c"]
+        );
+    }
+
+    #[test]
+    fn posix_classes() {
+        let import = import_flex(
+            "%%\n[[:alpha:]_] return A;\n[[:digit:][:space:]]+ return N;\n[^[:alnum:]] return P;\n",
+        );
+        assert_eq!(
+            patterns(&import),
+            vec!["[a-zA-Z_]", "[0-9 \\t\\n\\r\\f\\v]+", "[^a-zA-Z0-9]"]
+        );
+    }
+
+    #[test]
+    fn condition_scopes() {
+        let import = import_flex(
+            "%x COMMENT\n%s STR\n%%\n\"/*\" BEGIN(COMMENT);\n<COMMENT>{\n\"*/\" BEGIN(INITIAL);\n.|\\n ;\n}\n<STR>\\\" return Q;\n",
+        );
+
+        assert_eq!(
+            import.document.conditions,
+            vec![
+                ConditionSpec {
+                    name: "COMMENT".to_string(),
+                    exclusive: true,
+                },
+                ConditionSpec {
+                    name: "STR".to_string(),
+                    exclusive: false,
+                },
+            ]
+        );
+        assert_eq!(
+            rules(&import),
+            vec![
+                (
+                    String::new(),
+                    "\"/*\"".to_string(),
+                    Some("|ctx, _s| { ctx.begin(COMMENT); }"),
+                    None
+                ),
+                (
+                    "COMMENT".to_string(),
+                    "\"*/\"".to_string(),
+                    Some("|ctx, _s| { ctx.begin(INITIAL); }"),
+                    None
+                ),
+                (
+                    "COMMENT".to_string(),
+                    "[^\\n]|\\n".to_string(),
+                    Some("|_s| {}"),
+                    None
+                ),
+                ("STR".to_string(), "\\\"".to_string(), None, Some("Q")),
+            ]
+        );
+        assert!(import.notes.is_empty());
+    }
+
+    #[test]
+    fn shared_actions() {
+        let import = import_flex("%%\na |\nb |\nc return ABC;\nd |\ne printf(\"de\\n\");\n");
+        let rules = rules(&import);
+        let stub = "|_s| {\n    // printf(\"de\\n\");\n}";
+
+        assert_eq!(
+            rules,
+            vec![
+                (String::new(), "a".to_string(), None, Some("ABC")),
+                (String::new(), "b".to_string(), None, Some("ABC")),
+                (String::new(), "c".to_string(), None, Some("ABC")),
+                (String::new(), "d".to_string(), Some(stub), None),
+                (String::new(), "e".to_string(), Some(stub), None),
+            ]
+        );
+        assert_eq!(import.document.tokens, vec!["ABC"]);
+        // 同一个C动作只记录一次
+        assert_eq!(
+            import.notes,
+            vec![FlexNote {
+                line: 6,
+                message: "the C action is kept as a comment".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn returns_and_begins() {
+        let import = import_flex(
+            "%x S\n%%\nif return(IF);\n[0-9]+ { return NUMBER; }\n\"<\" BEGIN S;\n<S>\">\" { BEGIN(INITIAL); }\n",
+        );
+
+        assert_eq!(
+            rules(&import),
+            vec![
+                (String::new(), "if".to_string(), None, Some("IF")),
+                (String::new(), "[0-9]+".to_string(), None, Some("NUMBER")),
+                (
+                    String::new(),
+                    "\"<\"".to_string(),
+                    Some("|ctx, _s| { ctx.begin(S); }"),
+                    None
+                ),
+                (
+                    "S".to_string(),
+                    "\">\"".to_string(),
+                    Some("|ctx, _s| { ctx.begin(INITIAL); }"),
+                    None
+                ),
+            ]
+        );
+        assert_eq!(import.document.tokens, vec!["IF", "NUMBER"]);
+        // 转换的结果是一个有效的spec
+        assert!(import.document.to_config("test.l").is_ok());
+    }
+
+    #[test]
+    fn comments_in_actions() {
+        let import = import_flex(
+            "%x C\n%%\n[ \\t]+ /* skip */\n\\n { /* newline */ }\nif return IF; // keyword\n\"/*\" { BEGIN(C); /* comment */ }\nx { puts(\"/* x */\"); }\n",
+        );
+
+        // 只有注释的action是空的, 字符串中的不是注释
+        assert_eq!(
+            rules(&import),
+            vec![
+                (String::new(), "[ \\t]+".to_string(), Some("|_s| {}"), None),
+                (String::new(), "\\n".to_string(), Some("|_s| {}"), None),
+                (String::new(), "if".to_string(), None, Some("IF")),
+                (
+                    String::new(),
+                    "\"/*\"".to_string(),
+                    Some("|ctx, _s| { ctx.begin(C); }"),
+                    None
+                ),
+                (
+                    String::new(),
+                    "x".to_string(),
+                    Some("|_s| {\n    // puts(\"/* x */\");\n}"),
+                    None
+                ),
+            ]
+        );
+        let notes: Vec<(usize, &str)> = import
+            .notes
+            .iter()
+            .map(|note| (note.line, note.message.as_str()))
+            .collect();
+        assert_eq!(notes, vec![(7, "the C action is kept as a comment")]);
+    }
+
+    #[test]
+    fn unsupported_notes() {
+        let import = import_flex(
+            "%{\n#include <stdio.h>\n%}\nhex-digit [0-9a-f]\n%%\n^#.* return HASH;\nend$ return END;\nfoo/bar return FOO;\n{hex-digit}+ return HEX;\n%%\nint main() {}\n",
+        );
+
+        let notes: Vec<(usize, &str)> = import
+            .notes
+            .iter()
+            .map(|note| (note.line, note.message.as_str()))
+            .collect();
+        assert_eq!(
+            notes,
+            vec![
+                (1, "C code in %{ %} is not supported, it is dropped"),
+                (
+                    6,
+                    "in `^#.*`: the `^` anchor is not supported, the rule also matches inside a line"
+                ),
+                (
+                    7,
+                    "in `end$`: the `$` anchor is not supported, the rule also matches before other characters"
+                ),
+                (
+                    8,
+                    "in `foo/bar`: trailing context `/bar` is not supported, it is dropped"
+                ),
+                (11, "the user code section is C code, it is dropped"),
+            ]
+        );
+        assert_eq!(
+            patterns(&import),
+            vec!["\\#[^\\n]*", "end", "foo", "{hex_digit}+"]
+        );
+        assert_eq!(import.document.definitions[0].name, "hex_digit");
+    }
+}
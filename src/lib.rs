@@ -1,12 +1,28 @@
+mod check;
 mod code_gen;
+mod compile;
 mod config;
 mod dfa;
+mod flex;
 pub mod mermaid;
 mod nfa;
+mod parser;
 mod regex_expr;
+mod spec_document;
+#[cfg(test)]
+mod test_util;
 
+pub use check::{
+    check, examples, progress_lints, shortest_examples, Conflict, Example, Examples, Lint,
+};
 pub use code_gen::gen_code;
-pub use config::parse_config;
+pub use compile::build_dfa;
+pub use config::{
+    parse_config, Backend, Condition, Config, Definition, ErrorPolicy, Options, Rule,
+};
 pub use dfa::{Dfa, DfaVertexRef, LookupTable};
+pub use flex::{import_flex, FlexImport, FlexNote};
 pub use nfa::{Nfa, NfaBuilder, NfaVertexRef};
-pub use regex_expr::RegexExpr;
+pub use parser::ParseError;
+pub use regex_expr::{RegexAst, RegexDefinition, RegexError, RegexExpr, RegexOptions};
+pub use spec_document::{ConditionSpec, DefinitionSpec, OptionValue, RuleSpec, SpecDocument};
@@ -1,5 +1,5 @@
 use rlex::gen_code;
-use std::{env, error::Error};
+use std::{env, error::Error, path::Path};
 
 fn main() {
     // let res = RegexExpr::to_charactors("a\\\\a");
@@ -10,45 +10,157 @@ fn main() {
         std::process::exit(1);
     });
 
-    run(args).unwrap_or_else(|err| {
+    let res = match args.command {
+        Command::Generate => run(args),
+        Command::Check => check(args),
+        Command::Examples => examples(args),
+        Command::Import => import(args),
+    };
+    res.unwrap_or_else(|err| {
         println!("{}", err);
         std::process::exit(1);
     });
 
-    println!(
-        r#"Done.
-Please add the following dependencies to your Cargo.toml:
-serde = {{ version = "1.0", features = ["derive"] }}
-serde_json = "1.0""#
-    );
+    println!("Done.");
+}
+
+/// check
+/// print the lints of each config file
+fn check(args: Args) -> Result<(), Box<dyn Error>> {
+    let mut warnings = 0;
+    for config_file in args.config_files.iter() {
+        let mut config = rlex::parse_config(config_file)?;
+        config.options.bytes |= args.bytes;
+        config.options.no_std |= args.no_std;
+        config.options.validate()?;
+
+        let dfa = rlex::build_dfa(&config)?;
+        for lint in rlex::check(&config, &dfa.lookup_table) {
+            let level = if lint.is_warning() {
+                warnings += 1;
+                "warning"
+            } else {
+                "note"
+            };
+            println!("{}: {}: {}", config_file, level, lint.message(&config));
+        }
+    }
+    println!("{} warning(s)", warnings);
+
+    Ok(())
+}
+
+/// examples
+/// print the shortest input each rule wins on and each pair of rules conflicts on
+fn examples(args: Args) -> Result<(), Box<dyn Error>> {
+    for config_file in args.config_files.iter() {
+        let mut config = rlex::parse_config(config_file)?;
+        config.options.bytes |= args.bytes;
+        config.options.no_std |= args.no_std;
+        config.options.validate()?;
+
+        let dfa = rlex::build_dfa(&config)?;
+        let examples = rlex::examples(&config, &dfa.lookup_table);
+
+        println!("{}:", config_file);
+        for (rule, example) in examples.rules.iter().enumerate() {
+            if config.rules[rule].eof {
+                continue;
+            }
+            let example = match example {
+                Some(example) => example.describe(&config),
+                None => "never wins".to_string(),
+            };
+            println!("  rule `{}`: {}", config.describe_rule(rule), example);
+        }
+
+        if !examples.conflicts.is_empty() {
+            println!("conflicts:");
+        }
+        for conflict in examples.conflicts.iter() {
+            println!(
+                "  `{}` and `{}` both match {}, `{}` wins",
+                config.describe_rule(conflict.first),
+                config.describe_rule(conflict.second),
+                conflict.example.describe(&config),
+                config.describe_rule(conflict.winner)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// import
+/// convert a flex .l file, written as .rlex, .toml or .json by the extension of the output file
+/// constructs that were not converted are listed, the converted spec is checked after it is written
+fn import(args: Args) -> Result<(), Box<dyn Error>> {
+    let flex_file = &args.config_files[0];
+    let text = std::fs::read_to_string(flex_file)?;
+    let import = rlex::import_flex(&text);
+    for note in import.notes.iter() {
+        println!("{}:{}: unsupported: {}", flex_file, note.line, note.message);
+    }
+    println!("{} unsupported construct(s)", import.notes.len());
+
+    let document = import.document;
+    let output = match Path::new(&args.output_file)
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        Some("toml") => toml::to_string(&document)?,
+        Some("json") => serde_json::to_string_pretty(&document)?,
+        _ => document.to_rlex()?,
+    };
+    std::fs::write(&args.output_file, output)?;
+
+    // 转换的结果可能引用了被丢弃的definition
+    document.to_config(flex_file)?;
+
+    Ok(())
 }
 
 fn run(args: Args) -> Result<(), Box<dyn Error>> {
-    // 读取配置文件
-    let config = rlex::parse_config(&args.config_file)?;
+    let mut code = String::new();
+    let mut names: Vec<String> = Vec::new();
+    for config_file in args.config_files.iter() {
+        // 读取配置文件
+        let mut config = rlex::parse_config(config_file)?;
+        config.options.bytes |= args.bytes;
+        config.options.no_std |= args.no_std;
+        config.options.validate()?;
 
-    // 生成lookup_table和handler_funcs
-    let mut handler_funcs = Vec::new();
+        // 多个lexer生成到同一个文件时, 需要用%option name区分
+        if args.config_files.len() > 1 {
+            match &config.options.name {
+                Some(name) if names.contains(name) => {
+                    return Err(format!(
+                        "{}: lexer name \"{}\" is used by another config file",
+                        config_file, name
+                    )
+                    .into())
+                }
+                Some(name) => names.push(name.clone()),
+                None => {
+                    return Err(format!(
+                        "{}: \"%option name=NAME\" is required when generating several lexers into one file",
+                        config_file
+                    )
+                    .into())
+                }
+            }
+        }
 
-    let mut nfa_builder = rlex::NfaBuilder::new();
-    // build nfa
-    for (reg, handler_func) in config.rules.iter() {
-        let handler_id = handler_funcs.len();
-        handler_funcs.push(handler_func.clone());
+        // 生成lookup_table
+        let dfa = rlex::build_dfa(&config)?;
+        let lookup_table = dfa.lookup_table;
+        for lint in rlex::progress_lints(&config, &lookup_table) {
+            println!("{}: warning: {}", config_file, lint.message(&config));
+        }
 
-        nfa_builder.add_rule(&rlex::RegexExpr::build(reg)?, handler_id);
+        // 生成代码
+        code.push_str(&gen_code(&config, &lookup_table));
     }
-    let nfa = nfa_builder.build().unwrap();
-    let dfa = rlex::Dfa::build(&nfa);
-    let lookup_table = dfa.lookup_table;
-
-    // 生成代码
-    let code = gen_code(
-        &config.declarations,
-        &config.variables,
-        &lookup_table,
-        &handler_funcs,
-    );
 
     // 写入文件
     std::fs::write(&args.output_file, code)?;
@@ -64,20 +176,76 @@ fn run(args: Args) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Command
+/// generate: `rlex <config_file>... <output_file>`
+/// check: `rlex check <config_file>...`
+/// examples: `rlex examples <config_file>...`
+/// import: `rlex import <flex_file> <output_file>`
+enum Command {
+    Generate,
+    Check,
+    Examples,
+    Import,
+}
+
 struct Args {
-    config_file: String,
+    command: Command,
+    config_files: Vec<String>,
     output_file: String,
+    bytes: bool,
+    no_std: bool,
 }
 
 impl Args {
     fn build(args: Vec<String>) -> Result<Args, &'static str> {
-        if args.len() != 3 {
-            return Err("Usage: rlex <config_file> <output_file>");
+        const USAGE: &str = "Usage: rlex [--bytes] [--no-std] <config_file>... <output_file>
+       rlex check [--bytes] [--no-std] <config_file>...
+       rlex examples [--bytes] [--no-std] <config_file>...
+       rlex import <flex_file> <output_file>";
+
+        let mut args = args.into_iter().skip(1).peekable();
+        let command = match args.peek().map(String::as_str) {
+            Some("check") => {
+                args.next();
+                Command::Check
+            }
+            Some("examples") => {
+                args.next();
+                Command::Examples
+            }
+            Some("import") => {
+                args.next();
+                Command::Import
+            }
+            _ => Command::Generate,
+        };
+
+        let mut bytes = false;
+        let mut no_std = false;
+        let mut files = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "--bytes" => bytes = true,
+                "--no-std" => no_std = true,
+                _ if arg.starts_with("--") => return Err(USAGE),
+                _ => files.push(arg),
+            }
         }
 
+        // 生成代码时, 最后一个文件是输出文件
+        let output_file = match command {
+            Command::Check | Command::Examples if !files.is_empty() => String::new(),
+            Command::Generate if files.len() > 1 => files.pop().unwrap(),
+            Command::Import if files.len() == 2 => files.pop().unwrap(),
+            _ => return Err(USAGE),
+        };
+
         Ok(Args {
-            config_file: args[1].clone(),
-            output_file: args[2].clone(),
+            command,
+            config_files: files,
+            output_file,
+            bytes,
+            no_std,
         })
     }
 }
@@ -74,11 +74,11 @@ fn tarverse_nfa_vertex(
         .neighbors
         .iter()
         .for_each(|(&cond, vertex)| {
-            let neighbor_edges = tarverse_nfa_vertex(Rc::clone(&vertex), visited);
+            let neighbor_edges = tarverse_nfa_vertex(Rc::clone(vertex), visited);
             // 将结果添加到边中
             edges.push_str(&neighbor_edges);
 
-            let neighbor_id = find_vertex_id(Rc::clone(&vertex), visited);
+            let neighbor_id = find_vertex_id(Rc::clone(vertex), visited);
 
             // 添加当前节点到该节点的边
             edges.push_str(&format!("{}--{}-->{}\n", id, cond as char, neighbor_id));
@@ -91,11 +91,11 @@ fn tarverse_nfa_vertex(
         .epsilon_neighbors
         .iter()
         .for_each(|vertex| {
-            let neighbor_edges = tarverse_nfa_vertex(Rc::clone(&vertex), visited);
+            let neighbor_edges = tarverse_nfa_vertex(Rc::clone(vertex), visited);
             // 将结果添加到边中
             edges.push_str(&neighbor_edges);
 
-            let neighbor_id = find_vertex_id(Rc::clone(&vertex), visited);
+            let neighbor_id = find_vertex_id(Rc::clone(vertex), visited);
 
             // 添加当前节点到该节点的边
             edges.push_str(&format!("{}--{}-->{}\n", id, "ε", neighbor_id));
@@ -158,11 +158,11 @@ fn tarverse_dfa_vertex(vertex: DfaVertexRef, visited: &mut Vec<DfaVertexRef>) ->
         .neighbors
         .iter()
         .for_each(|(&cond, vertex)| {
-            let neighbor_edges = tarverse_dfa_vertex(DfaVertexRef::clone(&vertex), visited);
+            let neighbor_edges = tarverse_dfa_vertex(DfaVertexRef::clone(vertex), visited);
             // 将结果添加到边中
             edges.push_str(&neighbor_edges);
 
-            let neighbor_id = find_vertex_id(DfaVertexRef::clone(&vertex), visited);
+            let neighbor_id = find_vertex_id(DfaVertexRef::clone(vertex), visited);
 
             // 添加当前节点到该节点的边
             edges.push_str(&format!("{}--\"{}\"-->{}\n", id, cond as char, neighbor_id));
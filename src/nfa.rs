@@ -22,6 +22,12 @@ impl StateVertex {
 
 pub struct NfaVertexRef(Rc<RefCell<StateVertex>>);
 
+impl Default for NfaVertexRef {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NfaVertexRef {
     pub fn new() -> NfaVertexRef {
         NfaVertexRef(Rc::new(RefCell::new(StateVertex::new())))
@@ -54,6 +60,17 @@ pub struct Nfa {
 }
 
 impl Nfa {
+    /// empty
+    /// an nfa that accepts nothing
+    /// used for start conditions without any rule
+    pub fn empty() -> Nfa {
+        let vertex = NfaVertexRef::new();
+        Nfa {
+            start: NfaVertexRef::clone(&vertex),
+            end: vertex,
+        }
+    }
+
     pub fn build(expr: &RegexExpr, handler_id: usize) -> Nfa {
         let expr = &expr.0;
         let mut stack: Vec<Nfa> = Vec::new();
@@ -133,6 +150,34 @@ impl Nfa {
 
                     stack.push(curr_nfa);
                 }
+                Charactor::Plus => {
+                    // 对应正闭包的逻辑
+                    // 与闭包相同, 但不能直接跳过
+                    let left = stack.pop().unwrap();
+
+                    let curr_nfa = Nfa {
+                        start: NfaVertexRef::new(),
+                        end: NfaVertexRef::new(),
+                    };
+
+                    // 添加epsilon-move
+                    curr_nfa
+                        .start
+                        .borrow_mut()
+                        .epsilon_neighbors
+                        .push(NfaVertexRef::clone(&left.start));
+
+                    left.end
+                        .borrow_mut()
+                        .epsilon_neighbors
+                        .push(NfaVertexRef::clone(&curr_nfa.start));
+                    left.end
+                        .borrow_mut()
+                        .epsilon_neighbors
+                        .push(NfaVertexRef::clone(&curr_nfa.end));
+
+                    stack.push(curr_nfa);
+                }
                 Charactor::Concat => {
                     // 对应连接的逻辑
                     // 按照固定的公式处理
@@ -165,7 +210,35 @@ impl Nfa {
                         .insert(*c, NfaVertexRef::clone(&curr_nfa.end));
                     stack.push(curr_nfa);
                 }
-                _ => {}
+                Charactor::Set(bytes) => {
+                    // 字符集只需要一对节点, 每个字节一条边
+                    // 例如：S0 --a|b|c--> S1
+                    let curr_nfa = Nfa {
+                        start: NfaVertexRef::new(),
+                        end: NfaVertexRef::new(),
+                    };
+                    for c in bytes.iter() {
+                        curr_nfa
+                            .start
+                            .borrow_mut()
+                            .neighbors
+                            .insert(*c, NfaVertexRef::clone(&curr_nfa.end));
+                    }
+                    stack.push(curr_nfa);
+                }
+                Charactor::Empty => {
+                    // 空串，S0 --epsilon--> S1
+                    let curr_nfa = Nfa {
+                        start: NfaVertexRef::new(),
+                        end: NfaVertexRef::new(),
+                    };
+                    curr_nfa
+                        .start
+                        .borrow_mut()
+                        .epsilon_neighbors
+                        .push(NfaVertexRef::clone(&curr_nfa.end));
+                    stack.push(curr_nfa);
+                }
             }
         }
 
@@ -180,6 +253,12 @@ pub struct NfaBuilder {
     pub nfa: Option<Nfa>,
 }
 
+impl Default for NfaBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NfaBuilder {
     pub fn new() -> NfaBuilder {
         NfaBuilder { nfa: Option::None }
@@ -0,0 +1,875 @@
+use regex::Regex;
+use std::{error::Error, fmt};
+
+/// Span
+/// byte offsets of a piece of a .rlex file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Item
+/// a piece of a .rlex file, text is exactly the source at span
+#[derive(Debug, Clone)]
+pub struct Item {
+    pub text: String,
+    pub span: Span,
+}
+
+/// ParseError
+/// an error pointing at a span of a .rlex file, printed like rustc does
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    source_line: String,
+    marker: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = self.line.to_string();
+        let pad = " ".repeat(line.len());
+
+        writeln!(f, "parsing config error: {}", self.message)?;
+        writeln!(f, "{}--> {}:{}:{}", pad, self.path, self.line, self.column)?;
+        writeln!(f, "{} |", pad)?;
+        writeln!(f, "{} | {}", line, self.source_line)?;
+        write!(f, "{} | {}", pad, self.marker)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Source
+/// a .rlex file and its path, turns spans into ParseErrors
+#[derive(Clone, Copy)]
+pub struct Source<'a> {
+    pub path: &'a str,
+    pub text: &'a str,
+}
+
+impl<'a> Source<'a> {
+    /// error
+    /// an error at span, the marker stops at the end of the first line
+    pub fn error(&self, span: Span, message: impl Into<String>) -> ParseError {
+        let start = span.start.min(self.text.len());
+        let line_start = self.text[..start].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = self.text[start..]
+            .find('\n')
+            .map_or(self.text.len(), |index| start + index);
+        let source_line = self.text[line_start..line_end].trim_end_matches('\r');
+
+        // tab保持不变, 使^与源码对齐
+        let prefix = &self.text[line_start..start];
+        let mut marker = prefix
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let len = self.text[start..span.end.clamp(start, line_end)]
+            .chars()
+            .count();
+        marker.push_str(&"^".repeat(len.max(1)));
+
+        ParseError {
+            message: message.into(),
+            path: self.path.to_string(),
+            line: self.text[..start].matches('\n').count() + 1,
+            column: prefix.chars().count() + 1,
+            source_line: source_line.to_string(),
+            marker,
+        }
+    }
+
+    /// item
+    /// the trimmed source between start and end
+    fn item(&self, start: usize, end: usize) -> Item {
+        let text = &self.text[start..end];
+        let start = start + (text.len() - text.trim_start().len());
+        let end = end - (text.len() - text.trim_end().len());
+        let end = end.max(start);
+
+        Item {
+            text: self.text[start..end].to_string(),
+            span: Span { start, end },
+        }
+    }
+}
+
+/// SpecFile
+/// the sections of a .rlex file, before names and definitions are checked
+pub struct SpecFile {
+    pub declarations: String,
+    /// the paths of `%include "path"`, without quotes
+    pub includes: Vec<Item>,
+    pub directives: Vec<SpecDirective>,
+    pub definitions: Vec<SpecDefinition>,
+    pub rules: Vec<SpecRule>,
+    pub variables: String,
+}
+
+/// SpecDirective
+/// `%s`, `%x`, `%token` or `%option` and its arguments
+pub struct SpecDirective {
+    pub name: Item,
+    pub args: Vec<Item>,
+}
+
+/// SpecDefinition
+/// `name = pattern` or `name(param, ...) = pattern`
+pub struct SpecDefinition {
+    pub name: Item,
+    pub params: Vec<Item>,
+    pub pattern: Item,
+}
+
+/// SpecRule
+/// `<A,B>pattern -> action ;;` or `pattern => Kind ;;`
+/// conditions is empty if the rule has no prefix
+pub struct SpecRule {
+    pub conditions: Vec<Item>,
+    pub pattern: Item,
+    pub arrow: Item,
+    pub action: Item,
+}
+
+/// parse
+/// split a .rlex file into its sections and parse the definitions and rules
+pub fn parse(source: &Source) -> Result<SpecFile, ParseError> {
+    parse_file(source, false)
+}
+
+/// parse_include
+/// parse a file included by `%include`, every section is optional:
+/// without `%{` it starts with the definitions, without `%%` it is only definitions
+pub fn parse_include(source: &Source) -> Result<SpecFile, ParseError> {
+    parse_file(source, true)
+}
+
+fn parse_file(source: &Source, included: bool) -> Result<SpecFile, ParseError> {
+    let text = source.text;
+
+    // %{ declarations %}
+    let open = text.len() - text.trim_start().len();
+    let (declarations, definitions_start) = if text[open..].starts_with("%{") {
+        let close = match text[open..].find("%}") {
+            Some(index) => open + index,
+            None => {
+                return Err(source.error(
+                    Span {
+                        start: open,
+                        end: open + 2,
+                    },
+                    "\"%{\" is not closed by \"%}\"",
+                ))
+            }
+        };
+        (text[open + 2..close].to_string(), close + 2)
+    } else if included {
+        (String::new(), 0)
+    } else {
+        return Err(source.error(
+            Span {
+                start: open,
+                end: open + 1,
+            },
+            "expected \"%{\" at the start of the file",
+        ));
+    };
+
+    // %% 分隔definitions, rules和variables
+    let (definitions_end, rules_start) = match find_separator(source, definitions_start) {
+        Some(separator) => separator,
+        None if included => (text.len(), text.len()),
+        None => return Err(source.error(end_span(text), "expected \"%%\" before the rules")),
+    };
+    let (rules_end, variables_start) = match find_separator(source, rules_start) {
+        Some(separator) => separator,
+        None if included => (text.len(), text.len()),
+        None => return Err(source.error(end_span(text), "expected \"%%\" before the variables")),
+    };
+
+    let Definitions {
+        includes,
+        directives,
+        definitions,
+    } = parse_definitions(source, definitions_start, definitions_end)?;
+    let rules = parse_rules(source, rules_start, rules_end)?;
+
+    Ok(SpecFile {
+        declarations,
+        includes,
+        directives,
+        definitions,
+        rules,
+        variables: text[variables_start..].to_string(),
+    })
+}
+
+/// find_separator
+/// the next line that is only `%%`, as (start of the line, end of the line)
+fn find_separator(source: &Source, from: usize) -> Option<(usize, usize)> {
+    let mut start = from;
+    for line in source.text[from..].split_inclusive('\n') {
+        if line.trim() == "%%" {
+            return Some((start, start + line.len()));
+        }
+        start += line.len();
+    }
+    None
+}
+
+fn end_span(text: &str) -> Span {
+    Span {
+        start: text.len(),
+        end: text.len(),
+    }
+}
+
+/// Definitions
+/// the lines of the definitions section
+struct Definitions {
+    includes: Vec<Item>,
+    directives: Vec<SpecDirective>,
+    definitions: Vec<SpecDefinition>,
+}
+
+/// parse_definitions
+/// every line is a directive, `name = pattern`, a `// comment` or empty
+/// `%token { ... }` may span several lines
+fn parse_definitions(source: &Source, start: usize, end: usize) -> Result<Definitions, ParseError> {
+    let mut includes = Vec::new();
+    let mut directives = Vec::new();
+    let mut definitions = Vec::new();
+    // 未闭合的%token { 块
+    let mut token_block: Option<(SpecDirective, Span)> = None;
+
+    let mut line_start = start;
+    for line in source.text[start..end].split_inclusive('\n') {
+        let line_end = line_start + line.len();
+        let item = source.item(line_start, line_end);
+        line_start = line_end;
+
+        if let Some((mut directive, open)) = token_block.take() {
+            match item.text.find('}') {
+                Some(index) => {
+                    let close = item.span.start + index;
+                    directive
+                        .args
+                        .extend(words(source, item.span.start, close, true));
+                    directives.push(directive);
+                }
+                None => {
+                    directive
+                        .args
+                        .extend(words(source, item.span.start, item.span.end, true));
+                    token_block = Some((directive, open));
+                }
+            }
+            continue;
+        }
+
+        if item.text.is_empty() || item.text.starts_with("//") {
+            continue;
+        }
+
+        // %s, %x, %token, %option
+        if item.text.starts_with('%') {
+            let name_end = item
+                .text
+                .find(char::is_whitespace)
+                .map_or(item.span.end, |index| item.span.start + index);
+            let name = source.item(item.span.start, name_end);
+            match name.text.as_str() {
+                "%s" | "%x" | "%option" => {
+                    let args = words(source, name_end, item.span.end, false);
+                    directives.push(SpecDirective { name, args });
+                }
+                "%include" => {
+                    let path = source.item(name_end, item.span.end);
+                    let quoted = path.text.len() >= 2
+                        && path.text.starts_with('"')
+                        && path.text.ends_with('"');
+                    if !quoted {
+                        let span = if path.text.is_empty() {
+                            name.span
+                        } else {
+                            path.span
+                        };
+                        return Err(source.error(span, "expected a quoted path after %include"));
+                    }
+                    includes.push(Item {
+                        text: path.text[1..path.text.len() - 1].to_string(),
+                        span: path.span,
+                    });
+                }
+                "%token" => {
+                    let rest = source.item(name_end, item.span.end);
+                    if !rest.text.starts_with('{') {
+                        let args = words(source, name_end, item.span.end, true);
+                        directives.push(SpecDirective { name, args });
+                        continue;
+                    }
+
+                    let open = Span {
+                        start: rest.span.start,
+                        end: rest.span.start + 1,
+                    };
+                    let mut directive = SpecDirective { name, args: vec![] };
+                    match rest.text.find('}') {
+                        Some(index) => {
+                            let close = rest.span.start + index;
+                            directive.args = words(source, open.end, close, true);
+                            directives.push(directive);
+                        }
+                        None => {
+                            directive.args = words(source, open.end, rest.span.end, true);
+                            token_block = Some((directive, open));
+                        }
+                    }
+                }
+                _ => {
+                    return Err(source.error(
+                        name.span,
+                        format!(
+                        "unknown directive \"{}\", expected %s, %x, %token, %option or %include",
+                        name.text
+                    ),
+                    ))
+                }
+            }
+            continue;
+        }
+
+        // name = pattern, pattern中可以有=
+        let eq = match item.text.find('=') {
+            Some(index) => item.span.start + index,
+            None => return Err(source.error(item.span, "expected \"name = pattern\"")),
+        };
+        let name = source.item(item.span.start, eq);
+        let pattern = source.item(eq + 1, item.span.end);
+        // name(p1, p2) = pattern
+        let (name, params) = match name.text.find('(') {
+            Some(index) if name.text.ends_with(')') => {
+                let open = name.span.start + index;
+                let params = words(source, open + 1, name.span.end - 1, true);
+                (source.item(name.span.start, open), params)
+            }
+            _ => (name, Vec::new()),
+        };
+        if name.text.is_empty() || !name.text.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(source.error(
+                Span {
+                    start: name.span.start,
+                    end: eq,
+                },
+                format!("invalid definition name \"{}\"", name.text),
+            ));
+        }
+        check_params(source, &params)?;
+        if pattern.text.is_empty() {
+            return Err(source.error(
+                Span {
+                    start: eq,
+                    end: eq + 1,
+                },
+                format!("definition \"{}\" has no pattern", name.text),
+            ));
+        }
+        definitions.push(SpecDefinition {
+            name,
+            params,
+            pattern,
+        });
+    }
+
+    if let Some((_, open)) = token_block {
+        return Err(source.error(open, "\"%token {\" is not closed by \"}\""));
+    }
+
+    Ok(Definitions {
+        includes,
+        directives,
+        definitions,
+    })
+}
+
+/// check_params
+/// the params of a definition are names, each declared once
+pub(crate) fn check_params(source: &Source, params: &[Item]) -> Result<(), ParseError> {
+    for (index, param) in params.iter().enumerate() {
+        if !param.text.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(source.error(
+                param.span,
+                format!("invalid parameter name \"{}\"", param.text),
+            ));
+        }
+        if params[..index].iter().any(|each| each.text == param.text) {
+            return Err(source.error(
+                param.span,
+                format!("parameter \"{}\" declared twice", param.text),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// words
+/// the words between start and end, separated by whitespace, or commas too
+fn words(source: &Source, start: usize, end: usize, commas: bool) -> Vec<Item> {
+    let mut res = Vec::new();
+    let mut word_start = None;
+    for (index, c) in source.text[start..end].char_indices() {
+        let separator = c.is_whitespace() || (commas && c == ',');
+        match (separator, word_start) {
+            (true, Some(from)) => {
+                res.push(source.item(from, start + index));
+                word_start = None;
+            }
+            (false, None) => word_start = Some(start + index),
+            _ => {}
+        }
+    }
+    if let Some(from) = word_start {
+        res.push(source.item(from, end));
+    }
+    res
+}
+
+/// parse_rules
+/// rules are `<A,B>pattern -> action` or `pattern => Kind`, optionally followed by `;;`
+/// the pattern ends at the first `->` or `=>` on its line,
+/// lines starting with `//` without an arrow are comments,
+/// with an arrow they are rules whose pattern starts with `//`
+fn parse_rules(source: &Source, start: usize, end: usize) -> Result<Vec<SpecRule>, ParseError> {
+    let text = &source.text[..end];
+    let condition_prefix = Regex::new(r"^<(\*|\w+(?:\s*,\s*\w+)*)>").unwrap();
+    let mut res = Vec::new();
+    let mut pos = start;
+
+    loop {
+        // 跳过空白和注释
+        pos += text[pos..].len() - text[pos..].trim_start().len();
+        if pos >= end {
+            break;
+        }
+        if text[pos..].starts_with("//") && find_arrow(text, pos).is_none() {
+            pos = text[pos..].find('\n').map_or(end, |index| pos + index);
+            continue;
+        }
+
+        // <A,B>前缀
+        let mut conditions = Vec::new();
+        if let Some(prefix) = condition_prefix.captures(&text[pos..]) {
+            let names = prefix.get(1).unwrap();
+            conditions = words(source, pos + names.start(), pos + names.end(), true);
+            pos += prefix[0].len();
+        }
+
+        // pattern, 到->或=>为止
+        let pattern_start = pos;
+        let arrow_start = find_arrow(text, pos).ok_or_else(|| {
+            let line_end = text[pos..].find('\n').map_or(end, |index| pos + index);
+            source.error(
+                source.item(pattern_start, line_end).span,
+                "expected \"->\" or \"=>\" after the pattern",
+            )
+        })?;
+        let pattern = source.item(pattern_start, arrow_start);
+        let arrow = source.item(arrow_start, arrow_start + 2);
+        if pattern.text.is_empty() {
+            return Err(source.error(arrow.span, "expected a pattern before the arrow"));
+        }
+
+        // action, ;;可以省略
+        let action_start = arrow.span.end;
+        let (action_end, next) = if arrow.text == "=>" {
+            find_kind_end(text, action_start)
+        } else {
+            find_action_end(source, action_start)?
+        };
+        let action = source.item(action_start, action_end);
+        if action.text.is_empty() {
+            return Err(source.error(arrow.span, "expected an action after the arrow"));
+        }
+        pos = next;
+
+        res.push(SpecRule {
+            conditions,
+            pattern,
+            arrow,
+            action,
+        });
+    }
+
+    Ok(res)
+}
+
+/// find_arrow
+/// the offset of the first `->` or `=>` on the line,
+/// escaped characters, character classes and quoted strings are skipped
+fn find_arrow(text: &str, start: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut pos = start;
+    let mut in_class = false;
+    let mut in_string = false;
+
+    while pos < bytes.len() && bytes[pos] != b'\n' {
+        match bytes[pos] {
+            b'\\' => pos += 1,
+            b'[' if !in_string => in_class = true,
+            b']' if !in_string => in_class = false,
+            b'"' if !in_class => in_string = !in_string,
+            b'-' | b'=' if !in_class && !in_string && bytes.get(pos + 1) == Some(&b'>') => {
+                return Some(pos)
+            }
+            _ => {}
+        }
+        pos += 1;
+    }
+
+    None
+}
+
+/// find_kind_end
+/// a token kind ends at `;;` or at the end of its line
+/// return (end of the kind, start of the next rule)
+fn find_kind_end(text: &str, start: usize) -> (usize, usize) {
+    let line_end = text[start..]
+        .find('\n')
+        .map_or(text.len(), |index| start + index);
+    match text[start..line_end].find(";;") {
+        Some(index) => (start + index, start + index + 2),
+        None => (line_end, line_end),
+    }
+}
+
+/// find_action_end
+/// an action is a closure, `|s| { ... }`, or a block, `{ ... }`,
+/// a braced body ends at its balanced `}` and the `;;` after it is optional,
+/// any other action ends at `;;`
+/// return (end of the action, start of the next rule)
+fn find_action_end(source: &Source, start: usize) -> Result<(usize, usize), ParseError> {
+    let text = source.text;
+    let bytes = text.as_bytes();
+    let skip_whitespace = |pos: usize| pos + (text[pos..].len() - text[pos..].trim_start().len());
+
+    // move |params|
+    let mut pos = skip_whitespace(start);
+    if text[pos..].starts_with("move") && text[pos + 4..].trim_start().starts_with('|') {
+        pos = skip_whitespace(pos + 4);
+    }
+    if bytes.get(pos) == Some(&b'|') {
+        pos = match text[pos + 1..].find('|') {
+            Some(index) => skip_whitespace(pos + 1 + index + 1),
+            None => {
+                return Err(source.error(
+                    Span {
+                        start: pos,
+                        end: pos + 1,
+                    },
+                    "unclosed \"|\"",
+                ))
+            }
+        };
+    }
+
+    // { ... }
+    if bytes.get(pos) == Some(&b'{') {
+        let end = match find_block_end(text, pos) {
+            Some(end) => end,
+            None => {
+                return Err(source.error(
+                    Span {
+                        start: pos,
+                        end: pos + 1,
+                    },
+                    "unclosed \"{\" in the action",
+                ))
+            }
+        };
+
+        let next = skip_whitespace(end);
+        if text[next..].starts_with(";;") {
+            return Ok((end, next + 2));
+        }
+        return Ok((end, end));
+    }
+
+    // 其他action到;;为止
+    while pos < bytes.len() {
+        if text[pos..].starts_with(";;") {
+            return Ok((pos, pos + 2));
+        }
+        pos = skip_atom(text, pos);
+    }
+    Err(source.error(
+        source
+            .item(
+                start,
+                text[start..]
+                    .find('\n')
+                    .map_or(text.len(), |index| start + index),
+            )
+            .span,
+        "expected \";;\" after the action",
+    ))
+}
+
+/// find_block_end
+/// the offset after the `}` closing the `{` at start
+fn find_block_end(text: &str, start: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut pos = start;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(pos + 1);
+                }
+            }
+            _ => {}
+        }
+        pos = skip_atom(text, pos);
+    }
+
+    None
+}
+
+/// skip_atom
+/// the offset after the rust token at pos, as far as braces are concerned:
+/// string, raw string, byte string and char literals, comments, identifiers,
+/// or a single character
+fn skip_atom(text: &str, pos: usize) -> usize {
+    let bytes = text.as_bytes();
+    let is_ident = |c: u8| c.is_ascii_alphanumeric() || c == b'_';
+
+    match bytes[pos] {
+        // 字符串
+        b'"' => skip_string(bytes, pos + 1),
+        // 注释, 块注释可以嵌套
+        b'/' if bytes.get(pos + 1) == Some(&b'/') => text[pos..]
+            .find('\n')
+            .map_or(bytes.len(), |index| pos + index),
+        b'/' if bytes.get(pos + 1) == Some(&b'*') => {
+            let mut depth = 0;
+            let mut pos = pos;
+            while pos < bytes.len() {
+                if text[pos..].starts_with("/*") {
+                    depth += 1;
+                    pos += 2;
+                } else if text[pos..].starts_with("*/") {
+                    depth -= 1;
+                    pos += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    pos += 1;
+                }
+            }
+            pos
+        }
+        // 字符, 或者生命周期
+        b'\'' => {
+            if bytes.get(pos + 1) == Some(&b'\\') {
+                return text[pos + 2..]
+                    .find('\'')
+                    .map_or(bytes.len(), |index| pos + 2 + index + 1);
+            }
+            match text[pos + 1..].chars().next() {
+                Some(c) if text[pos + 1 + c.len_utf8()..].starts_with('\'') => {
+                    pos + 1 + c.len_utf8() + 1
+                }
+                _ => pos + 1,
+            }
+        }
+        // 标识符, r"..", r#".."#, br".."
+        c if is_ident(c) => {
+            let mut end = pos;
+            while end < bytes.len() && is_ident(bytes[end]) {
+                end += 1;
+            }
+            let word = &text[pos..end];
+            if (word == "r" || word == "br") && matches!(bytes.get(end), Some(b'"' | b'#')) {
+                return skip_raw_string(text, end);
+            }
+            if word == "b" && bytes.get(end) == Some(&b'"') {
+                return skip_string(bytes, end + 1);
+            }
+            end
+        }
+        _ => pos + text[pos..].chars().next().map_or(1, char::len_utf8),
+    }
+}
+
+/// code_atoms
+/// the rust tokens of code as skip_atom splits them, without whitespace and comments
+pub(crate) fn code_atoms(code: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut pos = 0;
+    while pos < code.len() {
+        let end = skip_atom(code, pos);
+        let atom = &code[pos..end];
+        if !(atom.trim().is_empty() || atom.starts_with("//") || atom.starts_with("/*")) {
+            res.push(atom);
+        }
+        pos = end;
+    }
+    res
+}
+
+/// skip_string
+/// the offset after the closing `"`, pos is after the opening one
+fn skip_string(bytes: &[u8], mut pos: usize) -> usize {
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'\\' => pos += 2,
+            b'"' => return pos + 1,
+            _ => pos += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// skip_raw_string
+/// the offset after `"##...`, pos is at the `#`s or `"` after r
+fn skip_raw_string(text: &str, pos: usize) -> usize {
+    let hashes = text[pos..].len() - text[pos..].trim_start_matches('#').len();
+    if !text[pos + hashes..].starts_with('"') {
+        return pos;
+    }
+
+    let close = format!("\"{}", "#".repeat(hashes));
+    text[pos + hashes + 1..]
+        .find(&close)
+        .map_or(text.len(), |index| pos + hashes + 1 + index + close.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(text: &str) -> Result<SpecFile, ParseError> {
+        parse(&Source {
+            path: "test.rlex",
+            text,
+        })
+    }
+
+    /// rules
+    /// the patterns and actions of the rules of text
+    fn rules(text: &str) -> Vec<(String, String)> {
+        parse_str(text)
+            .unwrap()
+            .rules
+            .into_iter()
+            .map(|rule| (rule.pattern.text, rule.action.text))
+            .collect()
+    }
+
+    #[test]
+    fn equals_in_patterns() {
+        let spec = parse_str(
+            "%{\n%}\n    Eq = ==|!=\n%%\n    a=b -> |_s| {} ;;\n    \"=>\"|[=>] => Arrow ;;\n%%\n",
+        )
+        .unwrap();
+
+        assert_eq!(spec.definitions[0].name.text, "Eq");
+        assert_eq!(spec.definitions[0].pattern.text, "==|!=");
+        assert_eq!(spec.rules[0].pattern.text, "a=b");
+        assert_eq!(spec.rules[1].pattern.text, "\"=>\"|[=>]");
+        assert_eq!(spec.rules[1].arrow.text, "=>");
+        assert_eq!(spec.rules[1].action.text, "Arrow");
+    }
+
+    #[test]
+    fn double_semicolons_in_actions() {
+        let actions = [
+            r#"|s| { println!(";;{}", s); }"#,
+            r##"|s| { let x = r#";; }"#; }"##,
+            r#"|_s| { let c = ';'; let d = '}'; let e = b";;"; }"#,
+            "|_s| {\n        // ;; }\n        /* ;; /* } */ */\n    }",
+            "|_s| { let _f = |x: &'static str| x; }",
+        ];
+        for action in actions {
+            let text = format!(
+                "%{{\n%}}\n%%\n    a -> {} ;;\n    b -> |_s| {{}} ;;\n%%\n",
+                action
+            );
+            let rules = rules(&text);
+            assert_eq!(rules.len(), 2, "{}", action);
+            assert_eq!(rules[0].1, action);
+            assert_eq!(rules[1].0, "b");
+        }
+    }
+
+    #[test]
+    fn optional_double_semicolons() {
+        let rules = rules(
+            "%{\n%}\n%%\n    a -> |_s| {}\n    b => B\n    c -> |_s| {\n        f();\n    }\n    d -> do_it() ;;\n%%\n",
+        );
+
+        assert_eq!(
+            rules,
+            vec![
+                ("a".to_string(), "|_s| {}".to_string()),
+                ("b".to_string(), "B".to_string()),
+                ("c".to_string(), "|_s| {\n        f();\n    }".to_string()),
+                ("d".to_string(), "do_it()".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn patterns_starting_with_slashes() {
+        let rules = rules(
+            "%{\n%}\n%%\n    // line comments\n    //[^\\n]* -> |_s| {}\n    // a comment\n    a => A\n%%\n",
+        );
+
+        assert_eq!(
+            rules,
+            vec![
+                ("//[^\\n]*".to_string(), "|_s| {}".to_string()),
+                ("a".to_string(), "A".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_positions() {
+        let err = parse_str("%{\n%}\n    Digit = [0-9]\n    = x\n%%\n%%\n")
+            .err()
+            .unwrap();
+        assert_eq!((err.line, err.column), (4, 5));
+        assert_eq!(err.message, "invalid definition name \"\"");
+
+        let err = parse_str("%{\n%}\n%%\n    a -> |_s| {}\n\tb c\n%%\n")
+            .err()
+            .unwrap();
+        assert_eq!((err.line, err.column), (5, 2));
+        assert_eq!(err.message, "expected \"->\" or \"=>\" after the pattern");
+        assert_eq!(
+            err.to_string(),
+            "parsing config error: expected \"->\" or \"=>\" after the pattern\n \
+--> test.rlex:5:2\n  |\n5 | \tb c\n  | \t^^^"
+        );
+
+        let err = parse_str("%{\n%}\n%%\n    a -> |_s| { f(\"}\");\n%%\n")
+            .err()
+            .unwrap();
+        assert_eq!((err.line, err.column), (4, 15));
+        assert_eq!(err.message, "unclosed \"{\" in the action");
+
+        let err = parse_str("%{\n%}\n").err().unwrap();
+        assert_eq!(err.message, "expected \"%%\" before the rules");
+    }
+}
@@ -1,251 +1,1108 @@
-use regex::Regex;
-use std::error::Error;
+use std::{collections::HashMap, error::Error, fmt};
 
-#[derive(PartialEq, Clone, Copy, Debug)]
+/// Charactor
+/// a symbol of a regex in postfix form
+/// Empty matches the empty string, Set matches any of its bytes
+#[derive(PartialEq, Clone, Debug)]
 pub enum Charactor {
     Char(u8),
-    LeftBracket,
-    RightBracket,
+    Set(Vec<u8>),
+    Empty,
     Closure,
+    Plus,
     Concat,
     Or,
 }
 
+/// RegexExpr
+/// a regex in postfix form, as the nfa builder reads it
 pub struct RegexExpr(pub Vec<Charactor>);
 
+/// RegexOptions
+/// how an ast is turned into bytes
+/// caseless: ascii letters match both cases
+/// bytes: the input is any bytes, `[^...]` matches every other byte
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RegexOptions {
+    pub caseless: bool,
+    pub bytes: bool,
+}
+
 impl RegexExpr {
+    /// build
+    /// parse expr, it must not reference definitions
     pub fn build(expr: &str) -> Result<RegexExpr, Box<dyn Error>> {
-        let res: String = Self::to_simple_regex(expr)?;
-        let res = Self::to_charactors(&res)?;
-        let res = Self::to_explicit_concat_expr(&res);
-        if let Ok(res) = Self::to_postfix(&res) {
-            Ok(RegexExpr(res))
-        } else {
-            Err(format!("parsing RegexExpr error: invalid regex \"{}\"", expr).into())
+        let ast = RegexAst::parse(expr)?;
+        RegexExpr::from_ast(&ast, &RegexOptions::default())
+    }
+
+    /// from_ast
+    /// the postfix form of a resolved ast
+    pub fn from_ast(ast: &RegexAst, options: &RegexOptions) -> Result<RegexExpr, Box<dyn Error>> {
+        let mut res = Vec::new();
+        Self::push_postfix(ast, options, &mut res)?;
+        Ok(RegexExpr(res))
+    }
+
+    fn push_postfix(
+        ast: &RegexAst,
+        options: &RegexOptions,
+        res: &mut Vec<Charactor>,
+    ) -> Result<(), Box<dyn Error>> {
+        match ast {
+            RegexAst::Empty => res.push(Charactor::Empty),
+            // caseless时字母匹配大小写
+            RegexAst::Byte(c) if options.caseless && c.is_ascii_alphabetic() => {
+                res.push(Charactor::Set(vec![
+                    c.to_ascii_uppercase(),
+                    c.to_ascii_lowercase(),
+                ]))
+            }
+            RegexAst::Byte(c) => res.push(Charactor::Char(*c)),
+            RegexAst::Set(bytes) => res.push(Charactor::Set(bytes.clone())),
+            RegexAst::Concat(items) | RegexAst::Or(items) => {
+                let op = match ast {
+                    RegexAst::Concat(_) => Charactor::Concat,
+                    _ => Charactor::Or,
+                };
+                if items.is_empty() {
+                    res.push(Charactor::Empty);
+                }
+                for (index, item) in items.iter().enumerate() {
+                    Self::push_postfix(item, options, res)?;
+                    if index != 0 {
+                        res.push(op.clone());
+                    }
+                }
+            }
+            RegexAst::Closure(item) => {
+                Self::push_postfix(item, options, res)?;
+                res.push(Charactor::Closure);
+            }
+            RegexAst::Plus(item) => {
+                Self::push_postfix(item, options, res)?;
+                res.push(Charactor::Plus);
+            }
+            // x? 即 (x|空)
+            RegexAst::Optional(item) => {
+                Self::push_postfix(item, options, res)?;
+                res.push(Charactor::Empty);
+                res.push(Charactor::Or);
+            }
+            RegexAst::Class {
+                bytes,
+                chars,
+                negated,
+                references,
+                ..
+            } if references.is_empty() => {
+                Self::push_postfix(&class_ast(bytes, chars, *negated, options), options, res)?
+            }
+            RegexAst::Reference { .. } | RegexAst::Class { .. } => {
+                let (name, _) = ast.references()[0];
+                return Err(
+                    format!("parsing RegexExpr error: variable \"{}\" not defined", name).into(),
+                );
+            }
         }
+
+        Ok(())
     }
+}
 
-    /// to_simple_regex
-    /// 将复杂正则表达式转换为简单正则表达式
-    fn to_simple_regex(expr: &str) -> Result<String, Box<dyn Error>> {
-        let mut expr: String = expr.to_string();
+/// RegexAst
+/// a parsed regex
+/// operators are `|`, `*`, `+`, `?` and `( )`, `.` is the explicit concat operator
+/// `"..."` is a literal string, `[...]` and `[^...]` are character classes,
+/// `{name}` and `{name(arg, ...)}` reference a definition, a `{` not starting a reference is a literal
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegexAst {
+    /// matches the empty string
+    Empty,
+    Byte(u8),
+    /// any of the bytes
+    Set(Vec<u8>),
+    Concat(Vec<RegexAst>),
+    Or(Vec<RegexAst>),
+    /// x*
+    Closure(Box<RegexAst>),
+    /// x+
+    Plus(Box<RegexAst>),
+    /// x?
+    Optional(Box<RegexAst>),
+    /// `{name}` or `{name(arg, ...)}`, offset and len are the bytes of the reference
+    Reference {
+        name: String,
+        args: Vec<RegexAst>,
+        offset: usize,
+        len: usize,
+    },
+    /// `[...]` or `[^...]`, bytes and chars are the members before negation,
+    /// chars are inclusive ranges of non-ascii characters,
+    /// `{name}` items are kept in references until they are resolved
+    /// a class becomes bytes when the expr is built, with its options
+    Class {
+        bytes: Vec<u8>,
+        chars: Vec<(char, char)>,
+        negated: bool,
+        references: Vec<(String, usize)>,
+        offset: usize,
+        len: usize,
+    },
+}
 
-        // 替换[A-z]为(a|b|c|...|z)
-        for captures in Regex::new(r#"\[(.*?)\]"#)
-            .unwrap()
-            .captures_iter(&expr.clone())
-        {
-            let mut in_closure = captures[1].to_string();
-            let mut to_replace = String::new();
-            // 查找其中如A-z的字符，将其转换为(a|b|c|...|z)
-            for a2b in Regex::new(r#"(.)-(.)"#).unwrap().captures_iter(&expr) {
-                let mut a2b_replace = String::new();
-                let mut from = a2b[1].chars().next().unwrap();
-                let to = a2b[2].chars().next().unwrap();
+/// RegexDefinition
+/// a definition as references see it
+/// the ast of a definition without params is resolved,
+/// a definition with params is resolved each time it is used, with its arguments
+#[derive(Debug, Clone)]
+pub struct RegexDefinition {
+    pub params: Vec<String>,
+    pub ast: RegexAst,
+}
 
-                if to < from {
-                    return Err(
-                        format!("parsing RegexExpr error: invalid regex: {}-{}", from, to).into(),
-                    );
-                }
+/// RegexError
+/// offset and len are the bytes of the pattern the error is about
+#[derive(Debug)]
+pub struct RegexError {
+    pub message: String,
+    pub offset: usize,
+    pub len: usize,
+}
 
-                while from <= to {
-                    a2b_replace.push(from);
-                    from = (from as u8 + 1) as char;
-                }
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "parsing RegexExpr error: {}", self.message)
+    }
+}
 
-                // 替换
-                in_closure = in_closure.replace(&a2b[0], &a2b_replace);
+impl Error for RegexError {}
+
+impl RegexAst {
+    /// parse
+    /// parse a pattern, references are kept until resolve
+    pub fn parse(expr: &str) -> Result<RegexAst, RegexError> {
+        let mut parser = RegexParser {
+            expr,
+            pos: 0,
+            in_args: false,
+        };
+        let res = parser.parse_or()?;
+
+        // parse_or只会停在)
+        if parser.pos < expr.len() {
+            return Err(parser.error(parser.pos, 1, "unmatched \")\""));
+        }
+        Ok(res)
+    }
+
+    /// references
+    /// the names referenced by the ast, with the offsets of their `{`
+    pub fn references(&self) -> Vec<(&str, usize)> {
+        let mut res = Vec::new();
+        self.push_references(&mut res);
+        res
+    }
+
+    fn push_references<'a>(&'a self, res: &mut Vec<(&'a str, usize)>) {
+        match self {
+            RegexAst::Reference {
+                name, args, offset, ..
+            } => {
+                res.push((name, *offset));
+                args.iter().for_each(|arg| arg.push_references(res))
+            }
+            RegexAst::Class { references, .. } => res.extend(
+                references
+                    .iter()
+                    .map(|(name, offset)| (name.as_str(), *offset)),
+            ),
+            RegexAst::Concat(items) | RegexAst::Or(items) => {
+                items.iter().for_each(|item| item.push_references(res))
             }
+            RegexAst::Closure(item) | RegexAst::Plus(item) | RegexAst::Optional(item) => {
+                item.push_references(res)
+            }
+            _ => {}
+        }
+    }
 
-            // 为每个字符加上或
-            for (index, c) in in_closure.chars().enumerate() {
-                if index != 0 {
-                    to_replace.push('|');
+    /// resolve
+    /// replace every reference by its definition
+    pub fn resolve(
+        self,
+        definitions: &HashMap<String, RegexDefinition>,
+    ) -> Result<RegexAst, RegexError> {
+        self.resolve_with(definitions, &HashMap::new())
+    }
+
+    /// resolve_with
+    /// resolve, a `{name}` where name is a key of params is replaced by its value
+    pub fn resolve_with(
+        self,
+        definitions: &HashMap<String, RegexDefinition>,
+        params: &HashMap<String, RegexAst>,
+    ) -> Result<RegexAst, RegexError> {
+        let resolve_all = |items: Vec<RegexAst>| {
+            items
+                .into_iter()
+                .map(|item| item.resolve_with(definitions, params))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(match self {
+            RegexAst::Reference { name, args, .. }
+                if args.is_empty() && params.contains_key(&name) =>
+            {
+                params[&name].clone()
+            }
+            RegexAst::Reference {
+                name,
+                args,
+                offset,
+                len,
+            } => {
+                let definition = find_definition(definitions, &name, args.len(), offset, len)?;
+                if definition.params.is_empty() {
+                    return Ok(definition.ast.clone());
                 }
 
-                // 转义特殊字符
-                if c == '\\' || c == '.' || c == '(' || c == ')' || c == '*' || c == '|' {
-                    to_replace.push('\\');
+                // 用参数展开definition, 错误指向引用处
+                let bound = definition
+                    .params
+                    .iter()
+                    .cloned()
+                    .zip(resolve_all(args)?)
+                    .collect();
+                definition
+                    .ast
+                    .clone()
+                    .resolve_with(definitions, &bound)
+                    .map_err(|err| RegexError {
+                        message: format!("in {{{}(...)}}: {}", name, err.message),
+                        offset,
+                        len,
+                    })?
+            }
+            RegexAst::Class {
+                mut bytes,
+                mut chars,
+                negated,
+                references,
+                offset,
+                len,
+            } => {
+                for (name, ref_offset) in references {
+                    let ref_len = name.len() + 2;
+                    let value = match params.get(&name) {
+                        Some(value) => value.clone(),
+                        None => find_definition(definitions, &name, 0, ref_offset, ref_len)?
+                            .ast
+                            .clone(),
+                    };
+                    if !class_members(&value, &mut bytes, &mut chars) {
+                        return Err(RegexError {
+                            message: format!(
+                                "\"{{{}}}\" in a character class must be a character or a character class",
+                                name
+                            ),
+                            offset: ref_offset,
+                            len: ref_len,
+                        });
+                    }
                 }
+                check_class(&bytes, &chars, negated, offset, len)?;
+                RegexAst::Class {
+                    bytes,
+                    chars,
+                    negated,
+                    references: Vec::new(),
+                    offset,
+                    len,
+                }
+            }
+            RegexAst::Concat(items) => RegexAst::Concat(resolve_all(items)?),
+            RegexAst::Or(items) => RegexAst::Or(resolve_all(items)?),
+            RegexAst::Closure(item) => {
+                RegexAst::Closure(Box::new(item.resolve_with(definitions, params)?))
+            }
+            RegexAst::Plus(item) => {
+                RegexAst::Plus(Box::new(item.resolve_with(definitions, params)?))
+            }
+            RegexAst::Optional(item) => {
+                RegexAst::Optional(Box::new(item.resolve_with(definitions, params)?))
+            }
+            ast => ast,
+        })
+    }
+}
+
+/// find_definition
+/// the definition referenced by name, with args arguments
+fn find_definition<'a>(
+    definitions: &'a HashMap<String, RegexDefinition>,
+    name: &str,
+    args: usize,
+    offset: usize,
+    len: usize,
+) -> Result<&'a RegexDefinition, RegexError> {
+    let error = |message: String| RegexError {
+        message,
+        offset,
+        len,
+    };
 
-                to_replace.push(c);
+    match definitions.get(name) {
+        Some(definition) if definition.params.len() == args => Ok(definition),
+        Some(definition) => Err(error(format!(
+            "definition \"{}\" takes {} argument(s), {} given",
+            name,
+            definition.params.len(),
+            args
+        ))),
+        None => Err(error(format!("variable \"{}\" not defined", name))),
+    }
+}
+
+/// class_members
+/// add the characters matched by ast to a class
+/// false if ast is not a character or a character class
+fn class_members(ast: &RegexAst, bytes: &mut Vec<u8>, chars: &mut Vec<(char, char)>) -> bool {
+    match ast {
+        RegexAst::Byte(c) => bytes.push(*c),
+        RegexAst::Set(set) => bytes.extend(set),
+        RegexAst::Class {
+            bytes: members,
+            chars: member_chars,
+            negated: false,
+            ..
+        } => {
+            bytes.extend(members);
+            chars.extend(member_chars);
+        }
+        RegexAst::Or(items) => return items.iter().all(|item| class_members(item, bytes, chars)),
+        // 多字节字符
+        RegexAst::Concat(items) => {
+            let encoded: Option<Vec<u8>> = items
+                .iter()
+                .map(|item| match item {
+                    RegexAst::Byte(c) => Some(*c),
+                    _ => None,
+                })
+                .collect();
+            let text = encoded.and_then(|encoded| String::from_utf8(encoded).ok());
+            match text.map(|text| text.chars().collect::<Vec<_>>()).as_deref() {
+                Some([c]) => chars.push((*c, *c)),
+                _ => return false,
             }
-            // warp with ()
-            to_replace = format!("({})", to_replace);
+        }
+        _ => return false,
+    }
+
+    true
+}
+
+/// check_class
+/// the errors of a class, offset and len are the bytes of the class
+/// `[^...]` takes ascii only
+fn check_class(
+    bytes: &[u8],
+    chars: &[(char, char)],
+    negated: bool,
+    offset: usize,
+    len: usize,
+) -> Result<(), RegexError> {
+    let error = |message: &str| RegexError {
+        message: message.to_string(),
+        offset,
+        len,
+    };
+
+    if negated && (!chars.is_empty() || bytes.iter().any(|c| !c.is_ascii())) {
+        return Err(error("non-ascii characters can't be used in [^...]"));
+    }
+    if !negated && bytes.is_empty() && chars.is_empty() {
+        return Err(error("empty character class"));
+    }
 
-            // replace
-            expr = expr.replace(&captures[0], &to_replace);
+    Ok(())
+}
+
+/// class_ast
+/// the ast of a class of bytes and chars, caseless classes get both cases before negation
+/// `[^...]` matches every other ascii character and every non-ascii character,
+/// or every other byte in bytes mode
+fn class_ast(
+    bytes: &[u8],
+    chars: &[(char, char)],
+    negated: bool,
+    options: &RegexOptions,
+) -> RegexAst {
+    let mut members = [false; 256];
+    for c in bytes {
+        members[*c as usize] = true;
+        if options.caseless {
+            members[c.to_ascii_lowercase() as usize] = true;
+            members[c.to_ascii_uppercase() as usize] = true;
         }
+    }
+    if negated && options.bytes {
+        members.iter_mut().for_each(|each| *each = !*each);
+    } else if negated {
+        // 只对ascii取反, 非ascii字符整个匹配, 不会从字符中间切开
+        members[..0x80].iter_mut().for_each(|each| *each = !*each);
+    }
 
-        Ok(expr)
+    // 单字节为一个集合, 多字节字符按utf-8编码的字节范围分支
+    let mut items = Vec::new();
+    let set = (0..=255u8)
+        .filter(|c| members[*c as usize])
+        .collect::<Vec<_>>();
+    if !set.is_empty() {
+        items.push(RegexAst::Set(set));
     }
+    let mut ranges = chars.to_vec();
+    if negated && !options.bytes {
+        ranges.push(('\u{80}', char::MAX));
+    }
+    items.extend(utf8_ranges(ranges));
 
-    fn to_explicit_concat_expr(expr: &Vec<Charactor>) -> Vec<Charactor> {
-        let mut res = Vec::new();
-        res.push(Charactor::LeftBracket);
+    match items.len() {
+        1 => items.pop().unwrap(),
+        _ => RegexAst::Or(items),
+    }
+}
 
-        expr.iter().enumerate().for_each(|(index, &curr)| {
-            res.push(curr);
+/// RegexParser
+/// a recursive descent parser, pos is the next byte of expr
+/// in_args: parsing an argument of `{name(...)}`, a `,` outside groups ends it
+struct RegexParser<'a> {
+    expr: &'a str,
+    pos: usize,
+    in_args: bool,
+}
 
-            if curr == Charactor::LeftBracket || curr == Charactor::Or {
-                return;
-            }
+/// ClassItem
+/// an end of a range in a character class
+#[derive(Clone, Copy)]
+enum ClassItem {
+    Byte(u8),
+    Char(char),
+}
+
+impl<'a> RegexParser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.expr.as_bytes().get(self.pos).copied()
+    }
+
+    fn error(&self, offset: usize, len: usize, message: impl Into<String>) -> RegexError {
+        RegexError {
+            message: message.into(),
+            offset,
+            len,
+        }
+    }
 
-            // 还有下一个，则查看下一个
-            if index + 1 < expr.len() {
-                let next = expr[index + 1];
+    /// a | b | ...
+    fn parse_or(&mut self) -> Result<RegexAst, RegexError> {
+        let mut items = vec![self.parse_concat()?];
+        while self.peek() == Some(b'|') {
+            self.pos += 1;
+            items.push(self.parse_concat()?);
+        }
+
+        Ok(if items.len() == 1 {
+            items.pop().unwrap()
+        } else {
+            RegexAst::Or(items)
+        })
+    }
 
-                if next == Charactor::RightBracket
-                    || next == Charactor::Or
-                    || next == Charactor::Closure
+    /// a b ..., `.` between items is ignored
+    fn parse_concat(&mut self) -> Result<RegexAst, RegexError> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            match c {
+                b'|' | b')' => break,
+                b',' if self.in_args => break,
+                // 参数前后的空格不属于参数
+                b' ' if self.in_args
+                    && self.expr[self.pos..]
+                        .trim_start_matches(' ')
+                        .starts_with([',', ')']) =>
                 {
-                    return;
+                    break
                 }
+                b'.' => self.pos += 1,
+                _ => items.push(self.parse_repeat()?),
+            }
+        }
+
+        Ok(match items.len() {
+            0 => RegexAst::Empty,
+            1 => items.pop().unwrap(),
+            _ => RegexAst::Concat(items),
+        })
+    }
+
+    /// a*, a+, a?
+    fn parse_repeat(&mut self) -> Result<RegexAst, RegexError> {
+        let mut res = self.parse_atom()?;
+        loop {
+            res = match self.peek() {
+                Some(b'*') => RegexAst::Closure(Box::new(res)),
+                Some(b'+') => RegexAst::Plus(Box::new(res)),
+                Some(b'?') => RegexAst::Optional(Box::new(res)),
+                _ => break,
+            };
+            self.pos += 1;
+        }
 
-                res.push(Charactor::Concat);
+        Ok(res)
+    }
+
+    fn parse_atom(&mut self) -> Result<RegexAst, RegexError> {
+        let start = self.pos;
+        let c = self.expr[start..].chars().next().unwrap();
+        self.pos += c.len_utf8();
+
+        match c {
+            '(' => {
+                // 括号中的,不分隔参数
+                let in_args = std::mem::replace(&mut self.in_args, false);
+                let res = self.parse_or();
+                self.in_args = in_args;
+                let res = res?;
+                if self.peek() != Some(b')') {
+                    return Err(self.error(start, 1, "unclosed \"(\""));
+                }
+                self.pos += 1;
+                Ok(res)
             }
-        });
+            '*' | '+' | '?' => Err(self.error(
+                start,
+                1,
+                format!("\"{}\" has nothing to repeat, escape it as \"\\{}\"", c, c),
+            )),
+            '[' => self.parse_class(start),
+            '"' => self.parse_literal(start),
+            '{' => self.parse_reference(start),
+            '\\' => Ok(RegexAst::Byte(self.parse_escape(start)?)),
+            // 多字节字符作为一个整体
+            _ => Ok(utf8_bytes(c)),
+        }
+    }
 
-        res.push(Charactor::RightBracket);
-        res
+    /// {name}, {name(arg, ...)}, or a literal `{`
+    fn parse_reference(&mut self, start: usize) -> Result<RegexAst, RegexError> {
+        let rest = &self.expr[self.pos..];
+        let name_len = name_len(rest);
+        let name = rest[..name_len].to_string();
+
+        let args = match rest[name_len..].chars().next() {
+            _ if name_len == 0 => return Ok(RegexAst::Byte(b'{')),
+            Some('}') => {
+                self.pos += name_len + 1;
+                Vec::new()
+            }
+            Some('(') => {
+                self.pos += name_len + 1;
+                let args = self.parse_args(start)?;
+                if self.peek() != Some(b'}') {
+                    return Err(self.error(
+                        start,
+                        self.pos - start,
+                        format!("expected \"}}\" after the arguments of {{{}(...)", name),
+                    ));
+                }
+                self.pos += 1;
+                args
+            }
+            _ => return Ok(RegexAst::Byte(b'{')),
+        };
+
+        Ok(RegexAst::Reference {
+            name,
+            args,
+            offset: start,
+            len: self.pos - start,
+        })
     }
 
-    pub fn to_charactors(expr: &str) -> Result<Vec<Charactor>, Box<dyn Error>> {
-        let mut res = Vec::new();
-        let mut last = b' ';
-
-        for &curr in expr.as_bytes().iter() {
-            // 如果上一个字符是\，则表示当前字符是转义字符
-            if last == b'\\' {
-                last = b' ';
-                match curr {
-                    b'.' => {
-                        res.push(Charactor::Char(b'.'));
-                    }
-                    b'(' => {
-                        res.push(Charactor::Char(b'('));
-                    }
-                    b')' => {
-                        res.push(Charactor::Char(b')'));
-                    }
-                    b'*' => {
-                        res.push(Charactor::Char(b'*'));
-                    }
-                    b'|' => {
-                        res.push(Charactor::Char(b'|'));
-                    }
-                    b'\\' => {
-                        res.push(Charactor::Char(b'\\'));
-                    }
-                    b'r' => {
-                        res.push(Charactor::Char(b'\r'));
-                    }
-                    b'n' => {
-                        res.push(Charactor::Char(b'\n'));
-                    }
-                    b't' => {
-                        res.push(Charactor::Char(b'\t'));
-                    }
-                    b'0' => {
-                        res.push(Charactor::Char(b'\0'));
-                    }
-                    b'f' => {
-                        res.push(Charactor::Char(b'\x0C'));
-                    }
-                    b'v' => {
-                        res.push(Charactor::Char(b'\x0B'));
-                    }
-                    _ => {
-                        return Err(format!(
-                            "parsing RegexExpr error: invalid regex escape: \\{}",
-                            curr as char
-                        )
-                        .into());
-                    }
+    /// the arguments of {name(...)} after `(`, start is the position of `{`
+    fn parse_args(&mut self, start: usize) -> Result<Vec<RegexAst>, RegexError> {
+        let mut args = Vec::new();
+        if self.peek() == Some(b')') {
+            self.pos += 1;
+            return Ok(args);
+        }
+
+        loop {
+            while self.peek() == Some(b' ') {
+                self.pos += 1;
+            }
+            let in_args = std::mem::replace(&mut self.in_args, true);
+            let arg = self.parse_or();
+            self.in_args = in_args;
+            args.push(arg?);
+
+            while self.peek() == Some(b' ') {
+                self.pos += 1;
+            }
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b')') => {
+                    self.pos += 1;
+                    return Ok(args);
                 }
-            } else {
-                // 如果上一个字符不是\，则表示当前字符不是转义字符
-                match curr {
-                    b'(' => {
-                        res.push(Charactor::LeftBracket);
-                    }
-                    b')' => {
-                        res.push(Charactor::RightBracket);
-                    }
-                    b'*' => {
-                        res.push(Charactor::Closure);
-                    }
-                    b'|' => {
-                        res.push(Charactor::Or);
-                    }
-                    b'.' => {
-                        res.push(Charactor::Concat);
-                    }
-                    b'\\' => last = curr,
-                    _ => {
-                        res.push(Charactor::Char(curr));
+                _ => return Err(self.error(start, 1, "arguments of \"{\" not closed by \")\"")),
+            }
+        }
+    }
+
+    /// the byte after `\`, start is the position of `\`
+    fn parse_escape(&mut self, start: usize) -> Result<u8, RegexError> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error(start, 1, "\"\\\" at the end of the pattern")),
+        };
+        self.pos += 1;
+
+        Ok(match c {
+            b'r' => b'\r',
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'0' => b'\0',
+            b'f' => b'\x0C',
+            b'v' => b'\x0B',
+            // \xHH, 任意字节
+            b'x' => {
+                let byte = self
+                    .expr
+                    .get(self.pos..self.pos + 2)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match byte {
+                    Some(byte) => {
+                        self.pos += 2;
+                        byte
                     }
+                    None => return Err(self.error(start, 2, "invalid regex escape: \\x")),
+                }
+            }
+            // 转义的符号表示其本身
+            c if c.is_ascii_punctuation() || c == b' ' => c,
+            _ => {
+                let c = self.expr[self.pos - 1..].chars().next().unwrap();
+                return Err(self.error(
+                    start,
+                    1 + c.len_utf8(),
+                    format!("invalid regex escape: \\{}", c),
+                ));
+            }
+        })
+    }
+
+    /// "...", start is the position of the opening `"`
+    fn parse_literal(&mut self, start: usize) -> Result<RegexAst, RegexError> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    let escape = self.pos;
+                    self.pos += 1;
+                    bytes.push(self.parse_escape(escape)?);
                 }
+                Some(c) => {
+                    bytes.push(c);
+                    self.pos += 1;
+                }
+                None => return Err(self.error(start, 1, "unclosed \"\\\"\"")),
             }
         }
 
-        Ok(res)
+        Ok(match bytes.len() {
+            0 => RegexAst::Empty,
+            1 => RegexAst::Byte(bytes[0]),
+            _ => RegexAst::Concat(bytes.into_iter().map(RegexAst::Byte).collect()),
+        })
     }
 
-    /// to_postfix
-    /// 将中缀表达式转换为后缀表达式
-    pub fn to_postfix(expr: &Vec<Charactor>) -> Result<Vec<Charactor>, Box<dyn Error>> {
-        // 表达正则运算符的优先级
-        // other表示其他所有的优先级
-        let mut op_stack = Vec::new();
-        let mut res_stack = Vec::new();
-
-        expr.iter().for_each(|&curr| {
-            match curr {
-                Charactor::LeftBracket => {
-                    // ( 无条件入栈
-                    op_stack.push(curr);
-                }
-                Charactor::RightBracket => {
-                    // ） 出栈直到遇到 (
-                    while let Some(top) = op_stack.pop() {
-                        if top == Charactor::LeftBracket {
-                            break;
-                        }
-
-                        res_stack.push(top);
-                    }
+    /// [...] or [^...], start is the position of `[`
+    /// ranges are over characters, `{name}` items are added when they are resolved
+    fn parse_class(&mut self, start: usize) -> Result<RegexAst, RegexError> {
+        let negated = self.peek() == Some(b'^');
+        if negated {
+            self.pos += 1;
+        }
+
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut chars: Vec<(char, char)> = Vec::new();
+        let mut references: Vec<(String, usize)> = Vec::new();
+        loop {
+            // {name}
+            let rest = &self.expr[self.pos..];
+            if let Some(name) = rest.strip_prefix('{') {
+                let len = name_len(name);
+                if len > 0 && name[len..].starts_with('}') {
+                    references.push((name[..len].to_string(), self.pos));
+                    self.pos += len + 2;
+                    continue;
                 }
-                Charactor::Closure | Charactor::Or | Charactor::Concat => {
-                    let priority = |c: &Charactor| match c {
-                        Charactor::Closure => 2,
-                        Charactor::Or => 0,
-                        Charactor::Concat => 1,
-                        _ => 0,
-                    };
+            }
 
-                    // 弹栈直到栈顶元素为(或 优先级小于当前元素
-                    while let Some(&top) = op_stack.last() {
-                        if top == Charactor::LeftBracket || priority(&top) < priority(&curr) {
-                            break;
-                        }
+            let item_start = self.pos;
+            let from = match self.parse_class_item(start)? {
+                Some(item) => item,
+                None => break,
+            };
 
-                        res_stack.push(op_stack.pop().unwrap());
-                    }
-                    op_stack.push(curr);
+            // a-z, 最后的-表示其本身
+            let rest = &self.expr[self.pos..];
+            if !rest.starts_with('-') || rest.starts_with("-]") {
+                match from {
+                    ClassItem::Byte(c) => bytes.push(c),
+                    ClassItem::Char(c) => chars.push((c, c)),
+                }
+                continue;
+            }
+            self.pos += 1;
+            let to = match self.parse_class_item(start)? {
+                Some(item) => item,
+                None => return Err(self.error(start, 1, "unclosed \"[\"")),
+            };
+
+            let invalid_range = |parser: &Self| {
+                parser.error(
+                    item_start,
+                    parser.pos - item_start,
+                    format!("invalid range {}", &parser.expr[item_start..parser.pos]),
+                )
+            };
+            match (from, to) {
+                (ClassItem::Byte(from), ClassItem::Byte(to)) if from <= to => {
+                    bytes.extend(from..=to)
                 }
-                _ => {
-                    res_stack.push(curr);
+                (ClassItem::Byte(from), ClassItem::Char(to)) if from.is_ascii() => {
+                    bytes.extend(from..0x80);
+                    chars.push(('\u{80}', to));
                 }
+                (ClassItem::Char(from), ClassItem::Char(to)) if from <= to => {
+                    chars.push((from, to))
+                }
+                _ => return Err(invalid_range(self)),
             }
-        });
+        }
 
-        // 如果栈中还有元素，则表示遇到错误
-        if op_stack.len() != 0 {
-            return Err("".into());
+        // 引用在resolve时加入
+        if references.is_empty() {
+            check_class(&bytes, &chars, negated, start, self.pos - start)?;
         }
+        Ok(RegexAst::Class {
+            bytes,
+            chars,
+            negated,
+            references,
+            offset: start,
+            len: self.pos - start,
+        })
+    }
+
+    /// the next character of a class, None at the closing `]`
+    fn parse_class_item(&mut self, start: usize) -> Result<Option<ClassItem>, RegexError> {
+        let c = match self.expr[self.pos..].chars().next() {
+            Some(c) => c,
+            None => return Err(self.error(start, 1, "unclosed \"[\"")),
+        };
+        self.pos += c.len_utf8();
+
+        Ok(match c {
+            ']' => None,
+            '\\' => Some(ClassItem::Byte(self.parse_escape(self.pos - 1)?)),
+            c if c.is_ascii() => Some(ClassItem::Byte(c as u8)),
+            c => Some(ClassItem::Char(c)),
+        })
+    }
+}
+
+/// name_len
+/// the length of the name at the start of text
+fn name_len(text: &str) -> usize {
+    text.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(text.len())
+}
+
+/// utf8_bytes
+/// a character as the concat of its utf-8 bytes
+fn utf8_bytes(c: char) -> RegexAst {
+    let mut buf = [0; 4];
+    let bytes = c.encode_utf8(&mut buf).as_bytes();
+    if bytes.len() == 1 {
+        return RegexAst::Byte(bytes[0]);
+    }
+    RegexAst::Concat(bytes.iter().map(|c| RegexAst::Byte(*c)).collect())
+}
+
+/// utf8_ranges
+/// the utf-8 sequences of some ranges of characters,
+/// each sequence is a concat of byte ranges, as regex-syntax's Utf8Sequences
+fn utf8_ranges(mut ranges: Vec<(char, char)>) -> Vec<RegexAst> {
+    // 合并重叠或相邻的范围
+    ranges.sort();
+    let mut merged: Vec<(u32, u32)> = Vec::new();
+    for (from, to) in ranges {
+        match merged.last_mut() {
+            Some((_, last)) if from as u32 <= *last + 1 => *last = (*last).max(to as u32),
+            _ => merged.push((from as u32, to as u32)),
+        }
+    }
+
+    let mut sequences = Vec::new();
+    for (from, to) in merged {
+        push_utf8_sequences(from, to, &mut sequences);
+    }
+    sequences
+        .into_iter()
+        .map(|sequence| {
+            let mut items = sequence
+                .into_iter()
+                .map(|(lo, hi)| match lo == hi {
+                    true => RegexAst::Byte(lo),
+                    false => RegexAst::Set((lo..=hi).collect()),
+                })
+                .collect::<Vec<_>>();
+            match items.len() {
+                1 => items.pop().unwrap(),
+                _ => RegexAst::Concat(items),
+            }
+        })
+        .collect()
+}
+
+/// push_utf8_sequences
+/// split from..=to until every byte of the encoding is a range,
+/// then push the byte ranges of the sequence
+fn push_utf8_sequences(from: u32, to: u32, res: &mut Vec<Vec<(u8, u8)>>) {
+    // 跳过代理区, from和to都是字符, 不在代理区中
+    if from < 0xD800 && to > 0xDFFF {
+        push_utf8_sequences(from, 0xD7FF, res);
+        push_utf8_sequences(0xE000, to, res);
+        return;
+    }
+
+    // 编码长度不同的分开
+    for max in [0x7F, 0x7FF, 0xFFFF] {
+        if from <= max && to > max {
+            push_utf8_sequences(from, max, res);
+            push_utf8_sequences(max + 1, to, res);
+            return;
+        }
+    }
+
+    // 后面的字节不是完整的0x80..=0xBF时分开
+    for i in 1..4 {
+        let mask = (1u32 << (6 * i)) - 1;
+        if from & !mask != to & !mask {
+            if from & mask != 0 {
+                push_utf8_sequences(from, from | mask, res);
+                push_utf8_sequences((from | mask) + 1, to, res);
+                return;
+            }
+            if to & mask != mask {
+                push_utf8_sequences(from, (to & !mask) - 1, res);
+                push_utf8_sequences(to & !mask, to, res);
+                return;
+            }
+        }
+    }
+
+    let (mut lo, mut hi) = ([0; 4], [0; 4]);
+    let lo = char::from_u32(from)
+        .unwrap()
+        .encode_utf8(&mut lo)
+        .as_bytes();
+    let hi = char::from_u32(to).unwrap().encode_utf8(&mut hi).as_bytes();
+    res.push(lo.iter().copied().zip(hi.iter().copied()).collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Dfa, Nfa};
+
+    /// matches
+    /// whether pattern, without references, matches the whole input
+    fn matches(pattern: &str, input: &[u8]) -> bool {
+        matches_with(pattern, &RegexOptions::default(), input)
+    }
+
+    fn matches_with(pattern: &str, options: &RegexOptions, input: &[u8]) -> bool {
+        let expr = RegexExpr::from_ast(&RegexAst::parse(pattern).unwrap(), options).unwrap();
+        test_util::matches(&Dfa::build(&Nfa::build(&expr, 0)).lookup_table, input)
+    }
+
+    fn error(pattern: &str) -> RegexError {
+        RegexAst::parse(pattern).err().unwrap()
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("[a-c0-9_]", b"b"));
+        assert!(matches("[a-c0-9_]", b"7"));
+        assert!(matches("[a-c0-9_]", b"_"));
+        assert!(!matches("[a-c0-9_]", b"d"));
+        assert!(matches("[é-]", "é".as_bytes()));
+        assert!(matches("[é-]", b"-"));
+        assert!(!matches("[é-]", &"é".as_bytes()[..1]));
+        assert!(matches("[+*?|(]", b"|"));
+    }
+
+    #[test]
+    fn negated_classes() {
+        assert!(matches("[^a\\n]", b"b"));
+        assert!(!matches("[^a\\n]", b"a"));
+        assert!(!matches("[^a\\n]", b"\n"));
+        // 非ascii字符整个匹配
+        assert!(matches("[^a]", "é".as_bytes()));
+        assert!(matches("[^a]", "中".as_bytes()));
+        assert!(matches("[^a]", "🦀".as_bytes()));
+        assert!(!matches("[^a]", &"中".as_bytes()[..2]));
+        assert!(matches("[^\"]+", "ab é\n".as_bytes()));
+        assert!(!matches("[^\"]+", b"a\"b"));
+
+        assert_eq!(
+            error("x[^é]").message,
+            "non-ascii characters can't be used in [^...]"
+        );
+        assert_eq!(error("x[^é]").offset, 1);
+    }
+
+    #[test]
+    fn ranges_of_characters() {
+        assert!(matches("[一-龥]+", "中文字".as_bytes()));
+        assert!(!matches("[一-龥]", "a".as_bytes()));
+        assert!(!matches("[一-龥]", "〇".as_bytes()));
+        assert!(!matches("[一-龥]", &"中".as_bytes()[..2]));
+
+        // 和字符的范围比较, 包括跨编码长度和代理区的范围
+        let bounds = [
+            ('\u{80}', char::MAX),
+            ('\u{7FF}', '\u{800}'),
+            ('\u{D7FF}', '\u{E000}'),
+            ('é', '𐀀'),
+        ];
+        for (from, to) in bounds {
+            let expr = RegexExpr::build(&format!("[a{}-{}]", from, to)).unwrap();
+            let table = Dfa::build(&Nfa::build(&expr, 0)).lookup_table;
+            // 每个编码长度和续字节的边界, 加上间隔取的字符
+            let edges = [
+                0x80, 0x800, 0x1000, 0xD800, 0xE000, 0x10000, 0x40000, 0x110000,
+            ];
+            let samples = edges
+                .into_iter()
+                .chain([from as u32, to as u32 + 1])
+                .flat_map(|edge| [edge - 1, edge])
+                .chain((0..0x110000).step_by(61));
+            for c in samples.filter_map(char::from_u32) {
+                let matched = test_util::matches(&table, c.encode_utf8(&mut [0; 4]).as_bytes());
+                assert_eq!(matched, c == 'a' || (from..=to).contains(&c), "{:?}", c);
+            }
+        }
+    }
+
+    #[test]
+    fn caseless_classes() {
+        let caseless = RegexOptions {
+            caseless: true,
+            ..RegexOptions::default()
+        };
+        assert!(matches_with("[a-c]x", &caseless, b"Bx"));
+        assert!(matches_with("[a-c]x", &caseless, b"bX"));
+        // 先补全大小写再取反
+        assert!(!matches_with("[^a]", &caseless, b"a"));
+        assert!(!matches_with("[^a]", &caseless, b"A"));
+        assert!(matches_with("[^a]", &caseless, b"b"));
+        assert!(matches_with("[^a]", &caseless, "é".as_bytes()));
+        assert!(!matches_with("[^A-Z_]+", &caseless, b"ab"));
+        assert!(matches_with("[^A-Z_]+", &caseless, b"12"));
+    }
+
+    #[test]
+    fn negated_classes_of_bytes() {
+        let bytes = RegexOptions {
+            bytes: true,
+            ..RegexOptions::default()
+        };
+        // latin-1的é是一个字节
+        assert!(matches_with("[^,\\n]+", &bytes, b"caf\xE9"));
+        assert!(matches_with("[^,\\n]", &bytes, b"\xFF"));
+        assert!(!matches_with("[^,\\n]+", &bytes, b"caf\xE9,"));
+        assert!(!matches_with("[^,\\n]", &bytes, "é".as_bytes()));
+        assert!(!matches("[^,\\n]+", b"caf\xE9"));
+    }
+
+    #[test]
+    fn repeats() {
+        assert!(matches("ab+c?", b"ab"));
+        assert!(matches("ab+c?", b"abbbc"));
+        assert!(!matches("ab+c?", b"a"));
+        assert!(!matches("ab+c?", b"ac"));
+        assert!(!matches("ab+c?", b"abcc"));
+        assert!(matches("(ab)+", b"ababab"));
+        assert!(!matches("(ab)+", b""));
+        assert!(!matches("(ab)+", b"aba"));
+        assert!(matches("(a|b)*c", b"c"));
+        assert!(matches("(a|b)*c", b"abbac"));
+
+        assert_eq!(
+            error("a|+").message,
+            "\"+\" has nothing to repeat, escape it as \"\\+\""
+        );
+        assert_eq!(error("a|+").offset, 2);
+    }
+
+    #[test]
+    fn references_and_literal_braces() {
+        assert_eq!(
+            RegexAst::parse("{digit}x{pair(a, {b})}")
+                .unwrap()
+                .references(),
+            vec![("digit", 0), ("pair", 8), ("b", 17)]
+        );
+
+        // 不是{name}或{name(的{是字面量
+        assert!(RegexAst::parse("a{1,2}{ x}{")
+            .unwrap()
+            .references()
+            .is_empty());
+        assert!(matches("a{1,2}", b"a{1,2}"));
+        assert!(matches("{}", b"{}"));
+        assert!(matches("\\{name}", b"{name}"));
+        assert!(matches("\"{name}\"", b"{name}"));
+
+        let err = RegexExpr::build("a{digit}").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "parsing RegexExpr error: variable \"digit\" not defined"
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches("\\n\\t\\r\\0", b"\n\t\r\0"));
+        assert!(matches("\\x41\\x7e", b"A~"));
+        assert!(matches("\\xC3\\xA9", "é".as_bytes()));
+        assert!(matches("\\*\\\"\\\\\\ ", b"*\"\\ "));
+        assert!(matches("[\\]\\-\\n]", b"]"));
+        assert!(matches("[\\]\\-\\n]", b"-"));
+        assert!(!matches("[\\]\\-\\n]", b"\\"));
 
-        Ok(res_stack)
+        assert_eq!(error("ab\\q").message, "invalid regex escape: \\q");
+        assert_eq!(error("ab\\q").offset, 2);
+        assert_eq!(error("\\xZ1").message, "invalid regex escape: \\x");
+        assert_eq!(error("a\\").message, "\"\\\" at the end of the pattern");
     }
 }
@@ -0,0 +1,359 @@
+use crate::{
+    config::{build_config, SpecParts},
+    parser::{self, Item, ParseError, Source, Span, SpecDefinition, SpecDirective, SpecRule},
+    Config,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, error::Error};
+
+/// SpecDocument
+/// a lexer spec as data, for specs generated by other tools
+/// read from toml or json, the fields are the sections of a .rlex file
+/// patterns and handlers are written as in a .rlex file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpecDocument {
+    /// the code between `%{` and `%}`
+    pub declarations: String,
+    /// `%s` and `%x` start conditions
+    pub conditions: Vec<ConditionSpec>,
+    /// `%token` kinds
+    pub tokens: Vec<String>,
+    /// `%option`s by name
+    pub options: BTreeMap<String, OptionValue>,
+    pub definitions: Vec<DefinitionSpec>,
+    pub rules: Vec<RuleSpec>,
+    /// the fields after the last `%%`
+    pub variables: String,
+}
+
+/// ConditionSpec
+/// a start condition, exclusive conditions are declared by `%x`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConditionSpec {
+    pub name: String,
+    #[serde(default)]
+    pub exclusive: bool,
+}
+
+/// DefinitionSpec
+/// `name = pattern` or `name(param, ...) = pattern`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DefinitionSpec {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<String>,
+    pub pattern: String,
+}
+
+/// RuleSpec
+/// `<conditions>pattern -> action` or `<conditions>pattern => token`,
+/// exactly one of action and token is set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSpec {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<String>,
+    pub pattern: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// OptionValue
+/// `key = true` is `%option key`, `key = false` is `%option nokey`,
+/// `key = "value"` is `%option key=value`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OptionValue {
+    Flag(bool),
+    Value(String),
+}
+
+impl SpecDocument {
+    /// from_toml
+    /// read a document from toml, path is used in errors
+    pub fn from_toml(path: &str, text: &str) -> Result<SpecDocument, Box<dyn Error>> {
+        // toml的错误以换行结尾
+        toml::from_str(text).map_err(|err| {
+            format!(
+                "parsing config error: {}: {}",
+                path,
+                err.to_string().trim_end()
+            )
+            .into()
+        })
+    }
+
+    /// from_json
+    /// read a document from json, path is used in errors
+    pub fn from_json(path: &str, text: &str) -> Result<SpecDocument, Box<dyn Error>> {
+        serde_json::from_str(text)
+            .map_err(|err| format!("parsing config error: {}: {}", path, err).into())
+    }
+
+    /// to_rlex
+    /// the document written as a .rlex file
+    /// a rule with both or neither of action and token is an error, as in to_config
+    pub fn to_rlex(&self) -> Result<String, Box<dyn Error>> {
+        let mut res = String::new();
+        res.push_str("%{\n");
+        push_section(&mut res, &self.declarations);
+        res.push_str("%}\n");
+
+        for condition in self.conditions.iter() {
+            let name = if condition.exclusive { "%x" } else { "%s" };
+            res.push_str(&format!("    {} {}\n", name, condition.name));
+        }
+        if !self.tokens.is_empty() {
+            res.push_str(&format!("    %token {}\n", self.tokens.join(", ")));
+        }
+        for (key, value) in self.options.iter() {
+            let option = match value {
+                OptionValue::Flag(true) => key.clone(),
+                OptionValue::Flag(false) => format!("no{}", key),
+                OptionValue::Value(value) => format!("{}={}", key, value),
+            };
+            res.push_str(&format!("    %option {}\n", option));
+        }
+        for definition in self.definitions.iter() {
+            let params = match definition.params.is_empty() {
+                true => String::new(),
+                false => format!("({})", definition.params.join(", ")),
+            };
+            res.push_str(&format!(
+                "    {}{} = {}\n",
+                definition.name, params, definition.pattern
+            ));
+        }
+
+        res.push_str("%%\n");
+        for (index, rule) in self.rules.iter().enumerate() {
+            let conditions = match rule.conditions.is_empty() {
+                true => String::new(),
+                false => format!("<{}>", rule.conditions.join(",")),
+            };
+            let action = match (&rule.action, &rule.token) {
+                (None, Some(token)) => format!("=> {}", token),
+                (Some(action), None) => format!("-> {}", action),
+                _ => {
+                    return Err(format!(
+                        "parsing config error: rules[{}]: {}",
+                        index, EXACTLY_ONE_ACTION
+                    )
+                    .into())
+                }
+            };
+            res.push_str(&format!(
+                "    {}{} {} ;;\n",
+                conditions, rule.pattern, action
+            ));
+        }
+
+        res.push_str("%%\n");
+        push_section(&mut res, &self.variables);
+        Ok(res)
+    }
+
+    /// to_config
+    /// check the document as a .rlex file is checked and build its config
+    /// each entry is shown as a .rlex line in errors, named like `path: rules[2]`
+    pub fn to_config(&self, path: &str) -> Result<Config, Box<dyn Error>> {
+        let mut directives: Vec<(Entry, SpecDirective)> = Vec::new();
+        for (index, condition) in self.conditions.iter().enumerate() {
+            let name = if condition.exclusive { "%x" } else { "%s" };
+            let entry = Entry::new(format!("{}: conditions[{}]", path, index));
+            directives.push(entry.directive(name, &condition.name));
+        }
+        for (index, token) in self.tokens.iter().enumerate() {
+            let entry = Entry::new(format!("{}: tokens[{}]", path, index));
+            directives.push(entry.directive("%token", token));
+        }
+        for (key, value) in self.options.iter() {
+            let option = match value {
+                OptionValue::Flag(true) => key.clone(),
+                OptionValue::Flag(false) => format!("no{}", key),
+                OptionValue::Value(value) => format!("{}={}", key, value),
+            };
+            let entry = Entry::new(format!("{}: options.{}", path, key));
+            directives.push(entry.directive("%option", &option));
+        }
+
+        let definitions = self
+            .definitions
+            .iter()
+            .enumerate()
+            .map(|(index, definition)| {
+                Entry::new(format!("{}: definitions[{}]", path, index)).definition(definition)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let rules = self
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| Entry::new(format!("{}: rules[{}]", path, index)).rule(rule))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        build_config(SpecParts {
+            declarations: self.declarations.clone(),
+            directives: directives
+                .iter()
+                .map(|(entry, each)| (entry.source(), each))
+                .collect(),
+            definitions: definitions
+                .iter()
+                .map(|(entry, each)| (entry.source(), each, false))
+                .collect(),
+            rules: rules
+                .iter()
+                .map(|(entry, each)| (entry.source(), each))
+                .collect(),
+            variables: self.variables.clone(),
+        })
+    }
+}
+
+const EXACTLY_ONE_ACTION: &str = "a rule needs exactly one of \"action\" and \"token\"";
+
+/// push_section
+/// code of a section, on its own lines
+fn push_section(res: &mut String, code: &str) {
+    if !code.trim().is_empty() {
+        res.push_str(code.trim_matches('\n'));
+        res.push('\n');
+    }
+}
+
+/// Entry
+/// an entry of a document written as a .rlex line, so errors can point into it
+/// label names the entry in place of a path
+struct Entry {
+    label: String,
+    text: String,
+}
+
+impl Entry {
+    fn new(label: String) -> Entry {
+        Entry {
+            label,
+            text: String::new(),
+        }
+    }
+
+    fn source(&self) -> Source<'_> {
+        Source {
+            path: &self.label,
+            text: &self.text,
+        }
+    }
+
+    /// push
+    /// append text, returning it as an item
+    fn push(&mut self, text: &str) -> Item {
+        let start = self.text.len();
+        self.text.push_str(text);
+
+        Item {
+            text: text.to_string(),
+            span: Span {
+                start,
+                end: self.text.len(),
+            },
+        }
+    }
+
+    /// `%name arg`
+    fn directive(mut self, name: &str, arg: &str) -> (Entry, SpecDirective) {
+        let name = self.push(name);
+        self.push(" ");
+        let args = vec![self.push(arg)];
+
+        (self, SpecDirective { name, args })
+    }
+
+    /// `name(param, ...) = pattern`
+    fn definition(
+        mut self,
+        definition: &DefinitionSpec,
+    ) -> Result<(Entry, SpecDefinition), ParseError> {
+        let name = self.push(&definition.name);
+        let mut params = Vec::new();
+        if !definition.params.is_empty() {
+            self.push("(");
+            for (index, param) in definition.params.iter().enumerate() {
+                if index > 0 {
+                    self.push(", ");
+                }
+                params.push(self.push(param));
+            }
+            self.push(")");
+        }
+        let eq = self.push(" = ");
+        let pattern = self.push(&definition.pattern);
+
+        let source = self.source();
+        if name.text.is_empty() || !name.text.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(source.error(
+                name.span,
+                format!("invalid definition name \"{}\"", name.text),
+            ));
+        }
+        parser::check_params(&source, &params)?;
+        if pattern.text.is_empty() {
+            return Err(source.error(
+                eq.span,
+                format!("definition \"{}\" has no pattern", name.text),
+            ));
+        }
+
+        Ok((
+            self,
+            SpecDefinition {
+                name,
+                params,
+                pattern,
+            },
+        ))
+    }
+
+    /// `<A,B>pattern -> action` or `<A,B>pattern => token`
+    fn rule(mut self, rule: &RuleSpec) -> Result<(Entry, SpecRule), ParseError> {
+        let mut conditions = Vec::new();
+        if !rule.conditions.is_empty() {
+            self.push("<");
+            for (index, condition) in rule.conditions.iter().enumerate() {
+                if index > 0 {
+                    self.push(",");
+                }
+                conditions.push(self.push(condition));
+            }
+            self.push(">");
+        }
+        let pattern = self.push(&rule.pattern);
+        self.push(" ");
+        let (arrow, action) = match (&rule.action, &rule.token) {
+            (Some(action), None) => (self.push("->"), action),
+            (None, Some(token)) => (self.push("=>"), token),
+            _ => return Err(self.source().error(pattern.span, EXACTLY_ONE_ACTION)),
+        };
+        self.push(" ");
+        let action = self.push(action);
+
+        if pattern.text.is_empty() {
+            return Err(self.source().error(arrow.span, "rule has no pattern"));
+        }
+
+        Ok((
+            self,
+            SpecRule {
+                conditions,
+                pattern,
+                arrow,
+                action,
+            },
+        ))
+    }
+}
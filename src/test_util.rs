@@ -0,0 +1,40 @@
+use crate::{
+    config::{build_config, SpecParts},
+    parser::{self, Source},
+    Config, LookupTable,
+};
+use std::error::Error;
+
+/// parse_text
+/// the config of a .rlex text without includes
+pub fn parse_text(text: &str) -> Result<Config, Box<dyn Error>> {
+    let source = Source {
+        path: "test.rlex",
+        text,
+    };
+    let spec = parser::parse(&source)?;
+    build_config(SpecParts {
+        declarations: spec.declarations.clone(),
+        directives: spec.directives.iter().map(|each| (source, each)).collect(),
+        definitions: spec
+            .definitions
+            .iter()
+            .map(|each| (source, each, false))
+            .collect(),
+        rules: spec.rules.iter().map(|each| (source, each)).collect(),
+        variables: spec.variables.clone(),
+    })
+}
+
+/// matches
+/// whether the dfa of table matches the whole input from its first start state
+pub fn matches(table: &LookupTable, input: &[u8]) -> bool {
+    let mut state = table.starts[0];
+    for c in input {
+        match table.states[state].neighbors.get(c) {
+            Some(next) => state = *next,
+            None => return false,
+        }
+    }
+    !table.states[state].handlers.is_empty()
+}
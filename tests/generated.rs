@@ -0,0 +1,413 @@
+use std::{
+    fs::{self, File},
+    path::PathBuf,
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// run
+/// generate the lexer of spec, build it with main and return what it prints
+/// main is the body of a program where the lexer is `mod lexer`
+fn run(name: &str, spec: &str, main: &str) -> String {
+    run_with(name, spec, main, |_| {})
+}
+
+/// run_no_std
+/// run with the lexer generated by `--no-std`
+fn run_no_std(name: &str, spec: &str, main: &str) -> String {
+    run_with(name, spec, main, |config| config.options.no_std = true)
+}
+
+/// run_with
+/// run, the config is changed by change before generating, like the command line flags do
+fn run_with(name: &str, spec: &str, main: &str, change: impl Fn(&mut rlex::Config)) -> String {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join("generated")
+        .join(name);
+    fs::create_dir_all(&dir).unwrap();
+
+    let spec_file = dir.join("lexer.rlex");
+    fs::write(&spec_file, spec).unwrap();
+    let mut config = rlex::parse_config(spec_file.to_str().unwrap()).unwrap();
+    change(&mut config);
+    config.options.validate().unwrap();
+    let dfa = rlex::build_dfa(&config).unwrap();
+    fs::write(
+        dir.join("lexer.rs"),
+        rlex::gen_code(&config, &dfa.lookup_table),
+    )
+    .unwrap();
+    fs::write(
+        dir.join("main.rs"),
+        format!(
+            "mod lexer;\n#[allow(unused_imports)]\nuse lexer::*;\n\nfn main() {{\n{}\n}}\n",
+            main
+        ),
+    )
+    .unwrap();
+
+    // 编译并运行生成的lexer
+    let binary = dir.join(name);
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = Command::new(rustc)
+        .args(["--edition", "2021", "-A", "warnings", "-o"])
+        .arg(&binary)
+        .arg(dir.join("main.rs"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // 输出写到文件, 死循环的lexer超时后结束
+    let (stdout, stderr) = (dir.join("stdout"), dir.join("stderr"));
+    let mut child = Command::new(&binary)
+        .stdout(File::create(&stdout).unwrap())
+        .stderr(File::create(&stderr).unwrap())
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("{} did not stop in time", name);
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert!(status.success(), "{}", fs::read_to_string(&stderr).unwrap());
+    fs::read_to_string(&stdout).unwrap()
+}
+
+#[test]
+fn unput_keeps_spans_in_the_input() {
+    let spec = r#"%{
+%}
+    %token Word
+%%
+    @ -> |ctx, _s| { ctx.unput("xyz"); } ;;
+    [a-z]+ => Word ;;
+    " " -> |_ctx, _s| {} ;;
+%%
+"#;
+    let main = r#"
+    let tokens = Rlex {}.lex("ab @cd ef @@g");
+    for token in tokens {
+        println!("{} {}..{} {}", token, token.span.start, token.span.end, token.span.column);
+    }
+    let tokens = Rlex {}.lex_reader(&b"ab @cd ef @@g"[..]).unwrap();
+    for token in tokens {
+        println!("{} {}..{} {}", token, token.span.start, token.span.end, token.span.column);
+    }
+"#;
+
+    let expected = "<Word, ab> 0..2 1
+<Word, xyzcd> 4..6 5
+<Word, ef> 7..9 8
+<Word, xyz> 11..11 12
+<Word, xyzg> 12..13 13
+";
+    assert_eq!(run("unput", spec, main), expected.repeat(2));
+}
+
+#[test]
+fn rejecting_eof_handlers() {
+    let spec = r##"%{
+%}
+    %token Word
+%%
+    "#" -> |ctx, _s| { ctx.push_input(RlexBuffer::new("in")); } ;;
+    [a-z]+ => Word ;;
+    <<EOF>> -> |ctx, _s| { ctx.unput("x"); ctx.emit(TokenKind::Word); ctx.reject(); } ;;
+%%
+"##;
+    let main = r#"
+    let tokens = Rlex {}.lex("a#b");
+    for token in tokens {
+        println!("{}", token);
+    }
+"#;
+
+    // reject清除, 不影响恢复的输入
+    assert_eq!(
+        run("rejecting_eof_handlers", spec, main),
+        "<Word, a>\n<Word, in>\n<Word, b>\n"
+    );
+}
+
+#[test]
+fn no_std_emits_when_the_handler_is_done() {
+    let spec = r#"%{
+%}
+    %token Word, Num
+%%
+    [a-z]+ -> |ctx, _s| { ctx.emit(TokenKind::Word); ctx.reject(); } ;;
+    [a-z]+ -> |ctx, _s| { ctx.emit(TokenKind::Word); ctx.yyless(1); } ;;
+    [0-9]+ -> |ctx, _s| { ctx.emit(TokenKind::Num); } ;;
+%%
+"#;
+
+    // reject丢弃token, token的文本是yyless之后的
+    let expected = "<Word, a>\n<Word, b>\n<Num, 12>\n";
+    let main = r#"
+    let tokens = Rlex {}.lex("ab12");
+    for token in tokens {
+        println!("{}", token);
+    }
+"#;
+    assert_eq!(run("std_emit", spec, main), expected);
+    let main = r#"
+    Rlex {}.lex(b"ab12", &mut |token| println!("{}", token));
+"#;
+    assert_eq!(run_no_std("no_std_emit", spec, main), expected);
+}
+
+#[test]
+fn reject_falls_back_to_other_rules() {
+    let spec = r#"%{
+%}
+    %token Keyword, Word
+%%
+    if => Keyword ;;
+    zz -> |ctx, _s| { ctx.emit(TokenKind::Keyword); ctx.reject(); } ;;
+    [a-z]+ -> |ctx, s| { if s.len() > 3 { ctx.reject(); } else { ctx.emit(TokenKind::Word); } } ;;
+    " " -> || {} ;;
+%%
+"#;
+
+    // 同样长度的下一条规则, 然后是更短的匹配
+    let expected = "<Keyword, if>
+<Word, iff>
+<Word, y>
+<Word, zz>
+<Word, abc>
+<Word, de>
+";
+    let main = r#"
+    let tokens = Rlex {}.lex("if iffy zz abcde");
+    for token in tokens {
+        println!("{}", token);
+    }
+"#;
+    assert_eq!(run("reject", spec, main), expected);
+    let main = r#"
+    Rlex {}.lex(b"if iffy zz abcde", &mut |token| println!("{}", token));
+"#;
+    assert_eq!(run_no_std("no_std_reject", spec, main), expected);
+}
+
+#[test]
+fn yyless_yymore_and_unput() {
+    let spec = r##"%{
+%}
+    %token Pair, Letter, Quoted
+    %x STR
+%%
+    ab -> |ctx, _s| { ctx.yyless(1); ctx.emit(TokenKind::Pair); } ;;
+    \" -> |ctx, _s| { ctx.yymore(); ctx.begin(STR); } ;;
+    <STR>[^"]*\" -> |ctx, _s| { ctx.emit(TokenKind::Quoted); ctx.begin(INITIAL); } ;;
+    [a-z] => Letter ;;
+    # -> |ctx, _s| { ctx.unput("b"); ctx.unput("a"); } ;;
+%%
+"##;
+    let main = r##"
+    let tokens = Rlex {}.lex("ab\"x y\"#c");
+    for token in tokens {
+        println!("{} {}..{}", token, token.span.start, token.span.end);
+    }
+"##;
+
+    // unput的文本在#之后, 不占输入的偏移
+    assert_eq!(
+        run("yyless_yymore_unput", spec, main),
+        "<Pair, a> 0..1
+<Letter, b> 1..2
+<Quoted, \"x y\"> 2..7
+<Pair, a> 8..8
+<Letter, b> 8..8
+<Letter, c> 8..9
+"
+    );
+}
+
+#[test]
+fn eof_handlers() {
+    let spec = r#"%{
+%}
+    %token Word, Unterminated, End
+    %x STR
+%%
+    \" -> |ctx, _s| { ctx.begin(STR); } ;;
+    <STR>[a-z]+ => Word ;;
+    <STR><<EOF>> -> |ctx, _s| { ctx.emit(TokenKind::Unterminated); ctx.begin(INITIAL); ctx.unput("end"); } ;;
+    [a-z]+ => Word ;;
+    <<EOF>> => End ;;
+%%
+"#;
+
+    // 每个start condition有自己的<<EOF>>, unput之后继续
+    let expected = "<Word, ab>
+<Word, cd>
+<Unterminated, >
+<Word, end>
+<End, >
+";
+    let main = r#"
+    let tokens = Rlex {}.lex("ab\"cd");
+    for token in tokens {
+        println!("{}", token);
+    }
+"#;
+    assert_eq!(run("eof", spec, main), expected);
+
+    // no_std没有unput
+    let spec = spec.replace(" ctx.unput(\"end\");", "");
+    let main = r#"
+    Rlex {}.lex(b"ab\"cd", &mut |token| println!("{}", token));
+"#;
+    assert_eq!(
+        run_no_std("no_std_eof", &spec, main),
+        "<Word, ab>\n<Word, cd>\n<Unterminated, >\n"
+    );
+}
+
+#[test]
+fn push_input() {
+    let spec = r##"%{
+%}
+%%
+    "#include" -> |ctx, _s| { ctx.push_input(RlexBuffer::new("inner text")); } ;;
+    @ -> |ctx, _s| { ctx.push_input(RlexBuffer::new("x")); ctx.push_input(RlexBuffer::new("y")); } ;;
+    [a-z]+ -> |ctx, s| { println!("{} {} {}:{}", s, ctx.input_depth(), ctx.line(), ctx.column()); } ;;
+    [ \n] -> || {} ;;
+%%
+"##;
+    let main = r#"
+    Rlex {}.lex("a #include b\n@ c");
+"#;
+
+    // 最后push的输入最先执行, 每个输入有自己的行列
+    assert_eq!(
+        run("push_input", spec, main),
+        "a 0 1:1
+inner 1 1:1
+text 1 1:7
+b 0 1:12
+y 2 1:1
+x 1 1:1
+c 0 2:3
+"
+    );
+}
+
+#[test]
+fn reader_refills() {
+    let spec = r#"%{
+%}
+    %token Word, Number, Space
+%%
+    [a-z]+ => Word ;;
+    [0-9]+ => Number ;;
+    [ \n]+ => Space ;;
+%%
+"#;
+    let main = r#"
+    // 每次只读3个字节, token跨过多次读取
+    struct Chunks<'a>(&'a [u8]);
+    impl std::io::Read for Chunks<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    let long = "x".repeat(20000);
+    let input = format!("abc 12345\nlonger words {} 7", long);
+    let tokens = Rlex {}.lex(&input);
+    let chunked = Rlex {}.lex_reader(Chunks(input.as_bytes())).unwrap();
+    let mut streamed = Vec::new();
+    Rlex {}
+        .lex_reader_with(input.as_bytes(), |token| streamed.push(token))
+        .unwrap();
+    assert!(tokens == chunked && tokens == streamed);
+    for token in tokens {
+        let text = if token.text.len() > 10 { "..." } else { &token.text };
+        println!("{} {:?} {}..{} {}:{}", token.kind, text, token.span.start, token.span.end, token.span.line, token.span.column);
+    }
+"#;
+
+    assert_eq!(
+        run("reader_refills", spec, main),
+        "Word \"abc\" 0..3 1:1
+Space \" \" 3..4 1:4
+Number \"12345\" 4..9 1:5
+Space \"\\n\" 9..10 1:10
+Word \"longer\" 10..16 2:1
+Space \" \" 16..17 2:7
+Word \"words\" 17..22 2:8
+Space \" \" 22..23 2:13
+Word \"...\" 23..20023 2:14
+Space \" \" 20023..20024 2:20014
+Number \"7\" 20024..20025 2:20015
+"
+    );
+}
+
+#[test]
+fn no_std_commands() {
+    let main = r#"
+    let mut rlex = Rlex { numbers: 0 };
+    let skipped = rlex.lex(b"SET led 1\nGET temp\n?SET x 22", &mut |token| {
+        println!("{} {}..{} {}:{}", token, token.span.start, token.span.end, token.span.line, token.span.column);
+    });
+    println!("{} {}", rlex.numbers, skipped);
+"#;
+
+    assert_eq!(
+        run_no_std("no_std_commands", include_str!("no_std/command.rlex"), main),
+        "<Set, SET> 0..3 1:1
+<Name, led> 4..7 1:5
+<Number, 1> 8..9 1:9
+<Newline, \\n> 9..10 1:10
+<Get, GET> 10..13 2:1
+<Name, temp> 14..18 2:5
+<Newline, \\n> 18..19 2:9
+<Set, SET> 20..23 3:2
+<Name, x> 24..25 3:6
+<Number, 22> 26..28 3:8
+<Error, > 28..28 3:10
+2 1
+"
+    );
+}
+
+#[test]
+fn bytes_mode() {
+    let spec = r#"%{
+%}
+    %token Field
+%%
+    [^,\n]+ => Field ;;
+    [,\n] -> || {} ;;
+%%
+"#;
+    let main = r#"
+    let tokens = Rlex {}.lex(b"caf\xE9,\xFF\xFE\n");
+    for token in tokens {
+        println!("{} {}..{}", token, token.span.start, token.span.end);
+    }
+"#;
+
+    // 和--bytes一样, [^...]对所有字节取反
+    assert_eq!(
+        run_with("bytes", spec, main, |config| config.options.bytes = true),
+        "<Field, caf\\xe9> 0..4\n<Field, \\xff\\xfe> 5..7\n"
+    );
+}
@@ -0,0 +1,13 @@
+use std::{env, fs, path::Path};
+
+fn main() {
+    println!("cargo:rerun-if-changed=command.rlex");
+
+    let mut config = rlex::parse_config("command.rlex").unwrap();
+    config.options.no_std = true;
+    let dfa = rlex::build_dfa(&config).unwrap();
+    let code = rlex::gen_code(&config, &dfa.lookup_table);
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("command.rs"), code).unwrap();
+}
@@ -0,0 +1,16 @@
+#![no_std]
+
+include!(concat!(env!("OUT_DIR"), "/command.rs"));
+
+/// count_commands
+/// lex input without allocation, return the number of commands
+pub fn count_commands(input: &[u8]) -> usize {
+    let mut rlex = Rlex { numbers: 0 };
+    let mut commands = 0;
+    rlex.lex(input, &mut |token| {
+        if matches!(token.kind, TokenKind::Set | TokenKind::Get) {
+            commands += 1;
+        }
+    });
+    commands
+}